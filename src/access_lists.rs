@@ -0,0 +1,225 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use openssl::hash::{hash, MessageDigest};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::server_properties::Properties;
+
+// The vanilla access lists, next to the server.
+pub const WHITELIST: &str = "whitelist.json";
+pub const OPS: &str = "ops.json";
+pub const BANNED_PLAYERS: &str = "banned-players.json";
+pub const BANNED_IPS: &str = "banned-ips.json";
+
+/// An entry of whitelist.json.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhitelistEntry {
+    pub uuid: String,
+    pub name: String
+}
+
+/// An entry of ops.json.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpEntry {
+    pub uuid: String,
+    pub name: String,
+    pub level: u8,
+    pub bypasses_player_limit: bool
+}
+
+/// An entry of banned-players.json.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BannedPlayer {
+    pub uuid: String,
+    pub name: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String
+}
+
+/// An entry of banned-ips.json.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BannedIp {
+    pub ip: String,
+    pub created: String,
+    pub source: String,
+    pub expires: String,
+    pub reason: String
+}
+
+/// All four lists at once, for showing them.
+#[derive(Serialize)]
+pub struct AccessLists {
+    pub whitelist: Vec<WhitelistEntry>,
+    pub ops: Vec<OpEntry>,
+    pub banned_players: Vec<BannedPlayer>,
+    pub banned_ips: Vec<BannedIp>
+}
+
+impl AccessLists {
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        Ok(Self {
+            whitelist: read_list(&dir.join(WHITELIST))?,
+            ops: read_list(&dir.join(OPS))?,
+            banned_players: read_list(&dir.join(BANNED_PLAYERS))?,
+            banned_ips: read_list(&dir.join(BANNED_IPS))?
+        })
+    }
+}
+
+/// Reads one of the lists, which the server only creates once something is in it.
+pub fn read_list<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>, String> {
+    match fs::read_to_string(path) {
+        Ok(contents) if contents.trim().is_empty() => Ok(vec![]),
+        Ok(contents) => serde_json::from_str(&contents).map_err(|err| format!("Failed to read {}: {err}", path.display())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+        Err(err) => Err(format!("Failed to read {}: {err}", path.display()))
+    }
+}
+
+pub fn write_list<T: Serialize>(path: &Path, list: &[T]) -> Result<(), String> {
+    let contents = serde_json::to_string_pretty(list).map_err(|err| err.to_string())?;
+    write_atomic(path, contents.as_bytes()).map_err(|err| format!("Failed to write {}: {err}", path.display()))
+}
+
+/// Removes every entry that matches, failing if there weren't any.
+pub fn remove_from<T: Serialize + DeserializeOwned>(path: &Path, matches: impl Fn(&T) -> bool) -> Result<(), String> {
+    let mut list: Vec<T> = read_list(path)?;
+    let before = list.len();
+    list.retain(|entry| !matches(entry));
+
+    if list.len() == before {
+        return Err(format!("Nobody like that is in {}", path.display()));
+    }
+    write_list(path, &list)
+}
+
+/// Writes the whole file or nothing, so a crash halfway through never leaves the server a broken list.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    fs::rename(&temporary, path)
+}
+
+/// The UUID an offline mode server gives a player, a version 3 UUID of "OfflinePlayer:<name>".
+pub fn offline_uuid(name: &str) -> String {
+    let digest = hash(MessageDigest::md5(), format!("OfflinePlayer:{name}").as_bytes()).expect("MD5 is always available");
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest);
+
+    // Mark it as version 3, in the RFC 4122 variant.
+    bytes[6] = bytes[6] & 0x0f | 0x30;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+/// Reads a UUID typed in by hand, with or without its dashes, into the form the lists use.
+pub fn parse_uuid(text: &str) -> Result<String, String> {
+    let hex: String = text.trim().chars().filter(|&c| c != '-').collect::<String>().to_ascii_lowercase();
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{} isn't a UUID", text.trim()));
+    }
+    Ok(format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..]))
+}
+
+/// Whether server.properties has the server checking players with Mojang, which it does unless told not to.
+pub fn online_mode(dir: &Path) -> bool {
+    Properties::load(dir).ok().and_then(|properties| properties.get("online-mode").map(|value| value != "false")).unwrap_or(true)
+}
+
+/// The UUID to put in the lists for a player.
+/// One the player was seen with wins, and offline mode servers can always work it out from the name.
+pub fn resolve_uuid(dir: &Path, name: &str, known: Option<String>) -> Result<String, String> {
+    if let Some(uuid) = known {
+        return Ok(uuid);
+    }
+    if !online_mode(dir) {
+        return Ok(offline_uuid(name));
+    }
+
+    Err(format!("The UUID of {name} isn't known yet, they have to join once first or you have to give it"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mcserver-handler-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn offline_uuid_matches_the_server() {
+        assert_eq!(offline_uuid("Notch"), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+    }
+
+    #[test]
+    fn parses_typed_uuids() {
+        assert_eq!(parse_uuid(" 069A79F444E94726A5BEFCA90E38AAF5 ").unwrap(), "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+        assert_eq!(parse_uuid("069a79f4-44e9-4726-a5be-fca90e38aaf5").unwrap(), "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+        assert!(parse_uuid("069a79f4-44e9-4726-a5be-fca90e38aaf").is_err());
+        assert!(parse_uuid("z69a79f4-44e9-4726-a5be-fca90e38aaf5").is_err());
+    }
+
+    #[test]
+    fn lists_round_trip() {
+        let dir = temp_dir("lists");
+
+        let whitelist = vec![WhitelistEntry { uuid: offline_uuid("Notch"), name: "Notch".to_string() }];
+        write_list(&dir.join(WHITELIST), &whitelist).unwrap();
+        let bans = vec![BannedPlayer {
+            uuid: offline_uuid("Griefer"),
+            name: "Griefer".to_string(),
+            created: "2023-05-01 12:00:00 +0000".to_string(),
+            source: "Admin".to_string(),
+            expires: "forever".to_string(),
+            reason: "Griefing".to_string()
+        }];
+        write_list(&dir.join(BANNED_PLAYERS), &bans).unwrap();
+
+        let lists = AccessLists::load(&dir).unwrap();
+        assert_eq!(lists.whitelist.len(), 1);
+        assert_eq!(lists.whitelist[0].name, "Notch");
+        assert_eq!(lists.whitelist[0].uuid, "b50ad385-829d-3141-a216-7e7d7539ba7f");
+        assert_eq!(lists.banned_players.len(), 1);
+        assert_eq!(lists.banned_players[0].reason, "Griefing");
+        assert!(lists.ops.is_empty());
+        assert!(lists.banned_ips.is_empty());
+
+        remove_from::<WhitelistEntry>(&dir.join(WHITELIST), |entry| entry.name == "Notch").unwrap();
+        assert!(read_list::<WhitelistEntry>(&dir.join(WHITELIST)).unwrap().is_empty());
+        assert!(remove_from::<WhitelistEntry>(&dir.join(WHITELIST), |entry| entry.name == "Notch").is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn uuids_for_online_mode_servers() {
+        let dir = temp_dir("uuids");
+
+        // Without server.properties the server is in online mode, so only a known UUID works.
+        assert!(resolve_uuid(&dir, "Notch", None).is_err());
+        assert_eq!(resolve_uuid(&dir, "Notch", Some("069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string())).unwrap(),
+                   "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+
+        fs::write(dir.join("server.properties"), "online-mode=false\n").unwrap();
+        assert_eq!(resolve_uuid(&dir, "Notch", None).unwrap(), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
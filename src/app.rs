@@ -0,0 +1,799 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+use handlebars::Handlebars;
+use serde_json::json;
+use crate::access_lists::AccessLists;
+use crate::auth::{Role, Session, Sessions};
+use crate::config::{Config, ServerConfig};
+use crate::event::Event;
+use crate::instance::Instance;
+use crate::lifecycle::LifecycleState;
+use crate::log_file::log;
+use crate::log_parser::Level;
+use crate::metrics::{self, Metrics};
+use crate::prometheus::{self, Exposition};
+use crate::search::{self, SearchQuery};
+use crate::server::{url_encode, Server, ServerStream};
+use crate::server_properties::{self, Properties};
+
+// How many moderation actions the players page shows.
+const RECENT_AUDIT: usize = 50;
+
+/// Owns all of the handler's state, and reacts to every event from the main loop.
+pub struct App {
+    pub config: Config,
+    // Shared with the threads that render slow pages, like search.
+    pub handlebars: Arc<Handlebars<'static>>,
+    pub server: Server,
+
+    // Every Minecraft server, in the order of the config.
+    // Pages pick one with ?server=name, and the first one is used without it.
+    pub instances: Vec<Instance>,
+
+    sessions: Sessions,
+    // When the handler started, for its uptime.
+    started: Instant,
+
+    // Set by /kill, the handler exits as soon as every server is down.
+    shutting_down: bool,
+    pub exit: bool
+}
+
+impl App {
+    pub fn new(config: Config, servers: Vec<ServerConfig>, handlebars: Arc<Handlebars<'static>>, server: Server, events: Sender<Event>) -> Self {
+        let labelled = servers.len() > 1;
+        let instances = servers.into_iter().enumerate().map(|(index, server)| {
+            let metrics = Metrics::new(Duration::from_secs(config.metrics_interval.max(1)), config.metrics_history);
+            Instance::new(index, server, labelled, metrics, events.clone())
+        }).collect();
+
+        Self {
+            config,
+            handlebars,
+            server,
+
+            instances,
+
+            sessions: Sessions::new(),
+            started: Instant::now(),
+
+            shutting_down: false,
+            exit: false
+        }
+    }
+
+    /// Starts every server, for when the handler starts up.
+    pub fn start_servers(&mut self) {
+        for instance in &mut self.instances {
+            instance.start();
+        }
+    }
+
+    pub fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Request(request) => self.handle_request(request),
+            Event::Stdin(input) => self.handle_stdin(input),
+            Event::Output(index, source, output) => {
+                if let Some(instance) = self.instances.get_mut(index) {
+                    instance.handle_output(source, output);
+                }
+            }
+            Event::ReloadTls => { let _ = self.reload_tls(); }
+            Event::OutputClosed(index) => {
+                if let Some(instance) = self.instances.get_mut(index) {
+                    instance.output_closed();
+                }
+                self.check_shutdown();
+            }
+            Event::Tick => {
+                for instance in &mut self.instances {
+                    instance.check_exited();
+                    instance.request_player_list();
+                    instance.sample_metrics();
+                    instance.request_tps();
+                    instance.check_lag();
+                }
+                self.check_shutdown();
+
+                // Pick up renewed certificates without needing a restart.
+                if self.config.watch_certificate {
+                    if let Some(result) = self.server.reload_if_changed() {
+                        print_reload_result(result);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a line typed into the handler's own console to a server.
+    /// `@name command` picks the server, anything else goes to the first one.
+    fn handle_stdin(&mut self, input: String) {
+        log!("Received {}", input.trim_end());
+        let input = input.trim_end_matches(['\r', '\n']);
+
+        let (index, command) = match input.strip_prefix('@').and_then(|rest| rest.split_once(' ')) {
+            Some((name, command)) => match self.find(name) {
+                Some(index) => (index, command),
+                None => {
+                    log!("There is no server called {name}");
+                    return;
+                }
+            },
+            None => (0, input)
+        };
+
+        self.instances[index].send_command(command.to_string());
+    }
+
+    /// Lets the handler exit after /kill, once nothing is running anymore.
+    fn check_shutdown(&mut self) {
+        if self.shutting_down && !self.instances.iter().any(|instance| instance.lifecycle.state().has_process()) {
+            self.exit = true;
+        }
+    }
+
+    /// The index of the server with this name.
+    fn find(&self, name: &str) -> Option<usize> {
+        self.instances.iter().position(|instance| instance.name() == name)
+    }
+
+    /// Saves the server.properties form, but only if every value is valid.
+    /// Returns the page to show, with the errors if there were any.
+    fn update_properties(&mut self, index: usize, request: &ServerStream, session: &Session) -> String {
+        let page = self.page(index, session);
+        let instance = &mut self.instances[index];
+        let dir = instance.config.server_dir();
+        let mut properties = match Properties::load(dir) {
+            Ok(properties) => properties,
+            Err(err) => return self.render_properties(index, page, &Properties::default(), &HashMap::new(), &HashMap::new(), Some(format!("Failed to read server.properties: {err}")))
+        };
+
+        // Known keys that aren't in the file yet are only added once they're filled in.
+        let submitted: HashMap<String, String> = properties.keys().into_iter()
+            .filter_map(|key| request.get_content(&key).map(|value| (key, value)))
+            .filter(|(key, value)| !value.is_empty() || properties.get(key).is_some())
+            .collect();
+        let errors: HashMap<String, String> = submitted.iter()
+            .filter_map(|(key, value)| server_properties::validate(key, value).err().map(|err| (key.clone(), err)))
+            .collect();
+
+        let state = instance.lifecycle.state();
+        let message = if !errors.is_empty() {
+            "Nothing was saved, fix the values below first.".to_string()
+        } else if state == LifecycleState::Starting {
+            // The server writes the file out again while it starts.
+            "The server is starting, try again once it's running.".to_string()
+        } else {
+            let mut changed: Vec<&str> = submitted.iter().filter(|(key, value)| properties.set(key, value)).map(|(key, _)| key.as_str()).collect();
+            changed.sort();
+
+            if changed.is_empty() {
+                "Nothing changed.".to_string()
+            } else if let Err(err) = properties.save(dir) {
+                format!("Failed to save server.properties: {err}")
+            } else {
+                log!("{} changed {} in the server.properties of {}", session.user, changed.join(", "), instance.name());
+                if state.has_process() {
+                    instance.properties_need_restart = true;
+                    "Saved. The server has to restart before it uses the new values.".to_string()
+                } else {
+                    "Saved. The server uses the new values the next time it starts.".to_string()
+                }
+            }
+        };
+
+        // Valid values that were saved read the same either way, so the form always shows what was sent.
+        self.render_properties(index, page, &properties, &submitted, &errors, Some(message))
+    }
+
+    fn render_properties(&self, index: usize, page: serde_json::Value, properties: &Properties, submitted: &HashMap<String, String>,
+                         errors: &HashMap<String, String>, message: Option<String>) -> String {
+        self.handlebars.render("properties", &with_page(page, json!({
+            "fields": properties.fields(submitted, errors),
+            "message": message,
+            "requires_restart": self.instances[index].properties_need_restart
+        }))).unwrap()
+    }
+
+    /// Swaps in the certificate on disk, keeping the old one if the new one is broken.
+    pub fn reload_tls(&mut self) -> Result<(), String> {
+        let result = self.server.reload_tls();
+        print_reload_result(result.clone());
+        result
+    }
+
+    /// Searches the logs on another thread, since going through old log files can take a while.
+    fn search(&self, index: usize, request: ServerStream, page: serde_json::Value) {
+        let instance = &self.instances[index];
+        let live = instance.lines();
+        let logs_dir = instance.config.server_path(&instance.config.server_logs_dir);
+        let handlebars = self.handlebars.clone();
+
+        thread::spawn(move || {
+            let api = request.get_route() == "GET /api/search";
+
+            let query = match SearchQuery::from_request(&request) {
+                Ok(query) => query,
+                Err(err) if api => {
+                    request.write_request("HTTP/1.1 400 Bad Request", &json!({"error": err}).to_string(), vec!["Content-Type: application/json"]);
+                    return;
+                }
+                Err(err) => {
+                    let contents = handlebars.render("search", &with_page(page, json!({"error": err, "form": form_values(&request)}))).unwrap();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                    return;
+                }
+            };
+
+            // An empty search would just dump every log, so only the form is shown.
+            if query.is_empty() && !api {
+                let contents = handlebars.render("search", &with_page(page, json!({"form": form_values(&request)}))).unwrap();
+                request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                return;
+            }
+
+            let results = search::search(&query, &live, &logs_dir);
+
+            if api {
+                request.write_request("HTTP/1.1 200 OK", &json!(results).to_string(), vec!["Content-Type: application/json"]);
+            } else {
+                let previous = (results.page > 1).then(|| query.page_link(&request, results.page - 1));
+                let next = (results.page < results.pages).then(|| query.page_link(&request, results.page + 1));
+                let contents = handlebars.render("search", &with_page(page, json!({
+                    "results": results,
+                    "previous": previous,
+                    "next": next,
+                    "form": form_values(&request)
+                }))).unwrap();
+                request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+            }
+        });
+    }
+
+    /// Whether the request may read /metrics, which only needs the token if there is one.
+    fn can_scrape(&self, request: &ServerStream) -> bool {
+        let token = match &self.config.metrics_token {
+            Some(token) => token,
+            None => return true
+        };
+
+        let sent = request.get_header("Authorization").and_then(|value| value.strip_prefix("Bearer ").map(str::to_string)).unwrap_or_default();
+        // Compared in constant time, so the token can't be guessed a byte at a time.
+        sent.len() == token.len() && openssl::memcmp::eq(sent.as_bytes(), token.as_bytes())
+    }
+
+    /// Everything /metrics reports, in the Prometheus text format.
+    fn prometheus_metrics(&self) -> String {
+        let mut exposition = Exposition::default();
+
+        exposition.header("mcserver_handler_uptime_seconds", "gauge", "How long the handler has been running.");
+        exposition.sample("mcserver_handler_uptime_seconds", &[], self.started.elapsed().as_secs_f64());
+
+        exposition.header("mcserver_state", "gauge", "1 for the state each server is in, 0 for the others.");
+        for instance in &self.instances {
+            let current = instance.lifecycle.state();
+            for state in LifecycleState::ALL {
+                exposition.sample("mcserver_state", &[("server", instance.name()), ("state", state.name())], if state == current { 1.0 } else { 0.0 });
+            }
+        }
+
+        exposition.header("mcserver_uptime_seconds", "gauge", "How long the server process has been up, 0 without one.");
+        for instance in &self.instances {
+            let uptime = instance.uptime().map(|uptime| uptime.as_secs_f64()).unwrap_or(0.0);
+            exposition.sample("mcserver_uptime_seconds", &[("server", instance.name())], uptime);
+        }
+
+        exposition.header("mcserver_restarts_total", "counter", "Times the server was started again after its first start.");
+        for instance in &self.instances {
+            let restarts = instance.lifecycle.times_entered(LifecycleState::Starting).saturating_sub(1);
+            exposition.sample("mcserver_restarts_total", &[("server", instance.name())], restarts as f64);
+        }
+
+        exposition.header("mcserver_crashes_total", "counter", "Times the server went down without being asked to.");
+        for instance in &self.instances {
+            exposition.sample("mcserver_crashes_total", &[("server", instance.name())], instance.lifecycle.times_entered(LifecycleState::Crashed) as f64);
+        }
+
+        exposition.header("mcserver_players_online", "gauge", "Players on the server right now.");
+        for instance in &self.instances {
+            exposition.sample("mcserver_players_online", &[("server", instance.name())], instance.players.lock().unwrap().online().len() as f64);
+        }
+
+        // Only servers with a process have anything to report here.
+        let samples: Vec<_> = self.instances.iter()
+            .filter(|instance| instance.running.is_some())
+            .filter_map(|instance| instance.metrics.latest().map(|sample| (instance.name(), sample)))
+            .collect();
+        exposition.header("mcserver_process_cpu_percent", "gauge", "CPU use of the server and everything it started, 100 per busy core.");
+        for (name, sample) in &samples {
+            exposition.sample("mcserver_process_cpu_percent", &[("server", name)], sample.cpu_percent);
+        }
+        exposition.header("mcserver_process_resident_memory_bytes", "gauge", "Memory the server and everything it started are using.");
+        for (name, sample) in &samples {
+            exposition.sample("mcserver_process_resident_memory_bytes", &[("server", name)], sample.rss_bytes as f64);
+        }
+        exposition.header("mcserver_process_threads", "gauge", "Threads of the server and everything it started.");
+        for (name, sample) in &samples {
+            exposition.sample("mcserver_process_threads", &[("server", name)], sample.threads as f64);
+        }
+        exposition.header("mcserver_process_open_fds", "gauge", "Open files and sockets of the server and everything it started.");
+        for (name, sample) in &samples {
+            exposition.sample("mcserver_process_open_fds", &[("server", name)], sample.open_fds as f64);
+        }
+
+        // Tick times only mean something while the server is up to answer.
+        let running: Vec<_> = self.instances.iter().filter(|instance| instance.lifecycle.state() == LifecycleState::Running).collect();
+        exposition.header("mcserver_tps", "gauge", "Ticks per second from the last answer to tps_command, 20 when keeping up.");
+        for instance in &running {
+            if let Some(sample) = instance.ticks.latest() {
+                exposition.sample("mcserver_tps", &[("server", instance.name())], sample.tps);
+            }
+        }
+        exposition.header("mcserver_mspt", "gauge", "Milliseconds per tick from the last answer to tps_command, for the commands that say.");
+        for instance in &running {
+            if let Some(mspt) = instance.ticks.latest().and_then(|sample| sample.mspt) {
+                exposition.sample("mcserver_mspt", &[("server", instance.name())], mspt);
+            }
+        }
+
+        exposition.header("mcserver_lagging", "gauge", "1 while a lag alert is raised, 0 otherwise.");
+        for instance in &self.instances {
+            exposition.sample("mcserver_lagging", &[("server", instance.name())], if instance.ticks.lagging() { 1.0 } else { 0.0 });
+        }
+        exposition.header("mcserver_cant_keep_up_total", "counter", "\"Can't keep up!\" warnings from the server.");
+        for instance in &self.instances {
+            exposition.sample("mcserver_cant_keep_up_total", &[("server", instance.name())], instance.ticks.warnings_total() as f64);
+        }
+
+        exposition.http();
+        exposition.finish()
+    }
+
+    /// Who sent this request, from their client certificate or their login cookie.
+    fn session(&self, request: &ServerStream) -> Option<Session> {
+        // A valid client certificate logs you straight in.
+        if let Some(client_name) = &request.client_name {
+            if let Some(client) = self.config.client_certificates.iter().find(|client| &client.common_name == client_name) {
+                return Some(Session { user: client.user.clone(), role: client.role, server_roles: client.server_roles.clone() });
+            }
+        }
+
+        self.sessions.get(&request.get_cookie("login").unwrap_or_default()).cloned()
+    }
+
+    /// Who this username and password belong to, if anyone.
+    fn password_user(&self, username: &str, password: &str) -> Option<Session> {
+        if username == self.config.main_user && password == self.config.main_pass {
+            return Some(Session::everywhere(username, Role::Admin));
+        }
+        if username == self.config.start_user && password == self.config.start_pass {
+            return Some(Session::everywhere(username, Role::Starter));
+        }
+
+        self.config.users.iter()
+            .find(|user| user.name == username && user.pass == password)
+            .map(|user| Session { user: user.name.clone(), role: user.role, server_roles: user.server_roles.clone() })
+    }
+
+    /// The session of whoever sent this request, if they have full access to the server.
+    fn admin(&self, request: &ServerStream, index: usize) -> Option<Session> {
+        self.session(request).filter(|session| session.allows(self.instances[index].name(), Role::Admin))
+    }
+
+    /// Whether the request comes from someone allowed to start the server.
+    fn can_start(&self, request: &ServerStream, index: usize) -> bool {
+        self.session(request).map(|session| session.allows(self.instances[index].name(), Role::Starter)).unwrap_or(false)
+    }
+
+    /// Whether the request comes from someone with full access to every server, for things that affect all of them.
+    fn is_admin_everywhere(&self, request: &ServerStream) -> bool {
+        self.session(request)
+            .map(|session| self.instances.iter().all(|instance| session.allows(instance.name(), Role::Admin)))
+            .unwrap_or(false)
+    }
+
+    /// The console of the first server someone has full access to.
+    fn home(&self, session: &Session) -> Option<String> {
+        self.instances.iter()
+            .find(|instance| session.allows(instance.name(), Role::Admin))
+            .map(|instance| format!("/console?server={}", instance.name()))
+    }
+
+    /// Where to send someone, staying on the same server.
+    fn location(&self, path: &str, index: usize) -> String {
+        format!("Location: {path}?server={}", self.instances[index].name())
+    }
+
+    /// What every page needs for the server switcher and its links.
+    /// Only the servers `session` may do something with are listed.
+    fn page(&self, index: usize, session: &Session) -> serde_json::Value {
+        let servers: Vec<serde_json::Value> = self.instances.iter().filter_map(|instance| {
+            let role = session.role_on(instance.name())?;
+            Some(json!({
+                "name": instance.name(),
+                "state": instance.lifecycle.state(),
+                "admin": role.allows(Role::Admin),
+                "current": instance.index == index
+            }))
+        }).collect();
+
+        json!({
+            "user": session.user,
+            "server": self.instances[index].name(),
+            "multiple_servers": self.instances.len() > 1,
+            "servers": servers
+        })
+    }
+
+    /// The state of every server, for the login page.
+    fn statuses(&self) -> Vec<serde_json::Value> {
+        self.instances.iter().map(|instance| json!({"name": instance.name(), "state": instance.lifecycle.state()})).collect()
+    }
+
+    fn render_login(&self, message: Option<String>) -> String {
+        self.handlebars.render("login", &json!({
+            "login_error": message,
+            "multiple_servers": self.instances.len() > 1,
+            "servers": self.statuses()
+        })).unwrap()
+    }
+
+    pub fn handle_request(&mut self, request: ServerStream) {
+        // Every page is about one server, the first one unless it says otherwise.
+        let index = match request.get_query("server") {
+            Some(name) => match self.find(&name) {
+                Some(index) => index,
+                None => {
+                    let contents = self.handlebars.render("404", &json!({})).unwrap();
+                    request.write_request("HTTP/1.1 404 not found", &contents, vec![]);
+                    return;
+                }
+            },
+            None => 0
+        };
+        let state = self.instances[index].lifecycle.state();
+
+        match request.get_route().as_str() {
+            "POST /console" => {
+                // Check for logged in
+                if self.admin(&request, index).is_some() {
+                    // They are logged in, so run the command and return a move to the GET /console
+                    if let Some(command) = request.get_content("command") {
+                        self.instances[index].send_command(command);
+                    }
+
+                    request.write_request("HTTP/1.1 303 See Other", "", vec![&self.location("/console", index)])
+                }
+                // If not logged in, send back to login page
+                else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            "GET /console" => {
+                // Check for logged in
+                if let Some(session) = self.admin(&request, index) {
+                    // They are logged in, so send them the console page for the current state.
+                    let page = self.page(index, &session);
+                    let contents = if state.has_process() {
+                        self.handlebars.render("template", &with_page(page, json!({"state": state}))).unwrap()
+                    } else {
+                        // The access lists can be edited while the server is down, so they're shown here.
+                        let (lists, error) = match AccessLists::load(self.instances[index].config.server_dir()) {
+                            Ok(lists) => (Some(lists), None),
+                            Err(err) => (None, Some(err))
+                        };
+                        let message = request.get_query("message").or(error);
+                        // What the last process printed, so it's clear why it stopped.
+                        let last_output: Vec<String> = self.instances[index].lines().iter().map(|line| line.text.clone()).collect();
+                        self.handlebars.render("offline", &with_page(page, json!({"state": state, "lists": lists, "message": message, "last_output": last_output}))).unwrap()
+                    };
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                }
+                // If not logged in, send back to login page
+                else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            "GET /" => {
+                // Check for logged in
+                match self.session(&request).and_then(|session| self.home(&session)) {
+                    Some(home) => request.write_request("HTTP/1.1 303 See Other", "", vec![&format!("Location: {home}")]),
+                    None => {
+                        let message = (self.instances.len() == 1).then(|| format!("Server Status: {state}"));
+                        let contents = self.render_login(message);
+                        request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                    }
+                }
+            }
+            "POST /" => {
+                // Check if username and password are correct.
+                let username = request.get_content("username").unwrap_or_default();
+                let password = request.get_content("password").unwrap_or_default();
+
+                if !self.config.password_login {
+                    // Only client certificates may log in.
+                    let contents = self.render_login(Some("Password login is turned off, use your client certificate".to_string()));
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                    return;
+                }
+
+                let session = match self.password_user(&username, &password) {
+                    Some(session) => session,
+                    None => {
+                        prometheus::record_failed_login();
+                        let contents = self.render_login(Some("Username or Password Is Incorrect".to_string()));
+                        request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                        return;
+                    }
+                };
+
+                if let Some(home) = self.home(&session) {
+                    // Generate a unique login token
+                    let login_token = self.sessions.login(session);
+
+                    // They should be logged in now.
+                    request.write_request("HTTP/1.1 303 See Other",
+                                          "",
+                                          vec![&format!("Location: {home}"), &format!("Set-Cookie: login={}; SameSite=Strict; Max-Age=86400", login_token)]);
+                    return;
+                }
+
+                // Someone wants to start a server, so run it if we can!
+                let index = request.get_content("server").and_then(|name| self.find(&name)).unwrap_or(index);
+                let instance = &mut self.instances[index];
+                let state = instance.lifecycle.state();
+                let message = if !session.allows(instance.name(), Role::Starter) {
+                    format!("You can't start {}", instance.name())
+                } else if state.can_start() {
+                    instance.start();
+                    "Server is starting".to_string()
+                } else {
+                    format!("Server is already {state}")
+                };
+                let contents = self.render_login(Some(message));
+                request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+            }
+            "GET /api/events" => {
+                if self.admin(&request, index).is_some() {
+                    let contents = json!({"events": self.instances[index].game_events.recent()}).to_string();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: application/json"])
+                } else {
+                    request.write_request("HTTP/1.1 401 Unauthorized", "User not logged in.", vec![])
+                }
+            }
+            "GET /players" => {
+                if let Some(session) = self.admin(&request, index) {
+                    let instance = &self.instances[index];
+                    let mut view = with_page(self.page(index, &session), instance.players.lock().unwrap().view());
+                    view["message"] = json!(request.get_query("message"));
+                    view["audit"] = json!(instance.audit.recent(RECENT_AUDIT).into_iter().map(|entry| json!({
+                        "time": entry.time.format("%Y-%m-%d %H:%M").to_string(),
+                        "user": entry.user,
+                        "action": entry.action.name(),
+                        "target": entry.target,
+                        "reason": entry.reason,
+                        "applied_by": entry.applied_by
+                    })).collect::<Vec<_>>());
+
+                    let contents = self.handlebars.render("players", &view).unwrap();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                } else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"]);
+                }
+            }
+            "POST /players" => {
+                match self.admin(&request, index) {
+                    Some(session) => {
+                        let message = self.instances[index].moderate(&request, &session.user).unwrap_or_else(|err| err);
+                        // The offline console edits the lists through here too.
+                        let back = if request.get_content("from").as_deref() == Some("console") { "/console" } else { "/players" };
+                        let location = format!("{}&message={}", self.location(back, index), url_encode(&message));
+                        request.write_request("HTTP/1.1 303 See Other", "", vec![&location]);
+                    }
+                    None => request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            "GET /properties" => {
+                if let Some(session) = self.admin(&request, index) {
+                    let page = self.page(index, &session);
+                    let contents = match Properties::load(self.instances[index].config.server_dir()) {
+                        Ok(properties) => self.render_properties(index, page, &properties, &HashMap::new(), &HashMap::new(), None),
+                        Err(err) => self.render_properties(index, page, &Properties::default(), &HashMap::new(), &HashMap::new(), Some(format!("Failed to read server.properties: {err}")))
+                    };
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                } else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"]);
+                }
+            }
+            "POST /properties" => {
+                match self.admin(&request, index) {
+                    Some(session) => {
+                        let contents = self.update_properties(index, &request, &session);
+                        request.write_request("HTTP/1.1 200 OK", &contents, vec![]);
+                    }
+                    None => request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            "GET /api/properties" => {
+                if self.admin(&request, index).is_some() {
+                    let instance = &self.instances[index];
+                    match Properties::load(instance.config.server_dir()) {
+                        Ok(properties) => {
+                            let contents = json!({
+                                "properties": properties.fields(&HashMap::new(), &HashMap::new()),
+                                "requires_restart": instance.properties_need_restart
+                            }).to_string();
+                            request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: application/json"])
+                        }
+                        Err(err) => request.write_request("HTTP/1.1 500 Internal Server Error", &format!("Failed to read server.properties: {err}"), vec![])
+                    }
+                } else {
+                    request.write_request("HTTP/1.1 401 Unauthorized", "User not logged in.", vec![])
+                }
+            }
+            "GET /api/players" => {
+                if self.admin(&request, index).is_some() {
+                    let players = self.instances[index].players.lock().unwrap();
+                    let contents = json!({"online": players.online(), "history": players.history()}).to_string();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: application/json"])
+                } else {
+                    request.write_request("HTTP/1.1 401 Unauthorized", "User not logged in.", vec![])
+                }
+            }
+            "GET /api/servers" => {
+                match self.session(&request) {
+                    Some(session) => {
+                        let contents = json!({"servers": self.page(index, &session)["servers"]}).to_string();
+                        request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: application/json"])
+                    }
+                    None => request.write_request("HTTP/1.1 401 Unauthorized", "User not logged in.", vec![])
+                }
+            }
+            "GET /api/metrics" => {
+                if self.admin(&request, index).is_some() {
+                    let metrics = &self.instances[index].metrics;
+                    let ticks = &self.instances[index].ticks;
+                    let contents = json!({
+                        "state": state,
+                        "interval_seconds": metrics.interval().as_secs(),
+                        "total_memory": metrics::total_memory(),
+                        "latest": metrics.latest(),
+                        "history": metrics.history(),
+                        "ticks": {
+                            "alert_below": ticks.alert_below(),
+                            "lagging": ticks.lagging(),
+                            "latest": ticks.latest(),
+                            "history": ticks.history(),
+                            "warnings": ticks.warnings()
+                        }
+                    }).to_string();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: application/json"])
+                } else {
+                    request.write_request("HTTP/1.1 401 Unauthorized", "User not logged in.", vec![])
+                }
+            }
+            "GET /metrics" => {
+                if self.can_scrape(&request) {
+                    let contents = self.prometheus_metrics();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: text/plain; version=0.0.4"])
+                } else {
+                    request.write_request("HTTP/1.1 401 Unauthorized", "A valid metrics_token is needed.", vec!["WWW-Authenticate: Bearer"])
+                }
+            }
+            "GET /data" => {
+                // Check for logged in
+                if self.admin(&request, index).is_some() {
+                    let mut lines = self.instances[index].lines();
+                    // Only lines at this level or worse, stack traces included.
+                    if let Some(level) = request.get_query("level").and_then(|level| Level::parse(&level)) {
+                        lines.retain(|line| line.level.map(|line_level| line_level >= level).unwrap_or(false));
+                    }
+                    let contents = json!({"state": state, "lines": lines}).to_string();
+                    request.write_request("HTTP/1.1 200 OK", &contents, vec!["Content-Type: application/json"])
+                } else {
+                    let contents = "User not logged in.";
+                    request.write_request("HTTP/1.1 401 Unauthorized", contents, vec![])
+                }
+            }
+            "GET /search" | "GET /api/search" => {
+                if let Some(session) = self.admin(&request, index) {
+                    let page = self.page(index, &session);
+                    self.search(index, request, page);
+                } else if request.get_route() == "GET /search" {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                } else {
+                    request.write_request("HTTP/1.1 401 Unauthorized", "User not logged in.", vec![])
+                }
+            }
+            "GET /logout" => {
+                // They should be logged out now.
+
+                // Remove their session from the system
+                self.sessions.logout(&request.get_cookie("login").unwrap_or_default());
+
+                // Remove their login cookie, and return them to the login page.
+                request.write_request("HTTP/1.1 303 See Other",
+                                      "",
+                                      vec!["Location: /", "Set-Cookie: login=0; SameSite=Strict; Max-Age=-1"]
+                );
+            }
+            "GET /start" => {
+                if self.can_start(&request, index) {
+                    self.instances[index].start();
+                    request.write_request("HTTP/1.1 303 See Other", "", vec![&self.location("/console", index)]);
+                } else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"]);
+                }
+            }
+            "GET /stop" => {
+                // Ensure Login, and if so, stop the server
+                if self.admin(&request, index).is_some() {
+                    self.instances[index].stop();
+                    request.write_request("HTTP/1.1 303 See Other", "", vec![&self.location("/console", index)]);
+                }
+                // If not logged in, send back to login page
+                else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            "GET /reload-tls" => {
+                if self.is_admin_everywhere(&request) {
+                    match self.reload_tls() {
+                        Ok(_) => request.write_request("HTTP/1.1 200 OK", "Certificate reloaded", vec![]),
+                        Err(err) => request.write_request("HTTP/1.1 500 Internal Server Error", &err, vec![])
+                    }
+                } else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            "GET /kill" => {
+                // Ensure Login, and if so, stop every server and then the handler.
+                if self.is_admin_everywhere(&request) {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec![&self.location("/console", index)]);
+
+                    // Wait for the servers to go down before killing the webserver too.
+                    self.shutting_down = true;
+                    for instance in &mut self.instances {
+                        instance.stop();
+                    }
+                    self.check_shutdown();
+                }
+                // If not logged in, send back to login page
+                else {
+                    request.write_request("HTTP/1.1 303 See Other", "", vec!["Location: /"])
+                }
+            }
+            _ => {
+                let contents = self.handlebars.render("404", &json!({})).unwrap();
+                request.write_request("HTTP/1.1 404 not found", &contents, vec![])
+            }
+        }
+    }
+}
+
+/// Adds the values of one page to what every page has.
+fn with_page(mut page: serde_json::Value, values: serde_json::Value) -> serde_json::Value {
+    if let (Some(page), serde_json::Value::Object(values)) = (page.as_object_mut(), values) {
+        page.extend(values);
+    }
+    page
+}
+
+/// What was typed into the search form, so it can be filled back in.
+fn form_values(request: &ServerStream) -> serde_json::Value {
+    let mut values = serde_json::Map::new();
+    for key in ["text", "regex", "level", "from", "to", "source", "context"] {
+        values.insert(key.to_string(), json!(request.get_query(key).unwrap_or_default()));
+    }
+    serde_json::Value::Object(values)
+}
+
+fn print_reload_result(result: Result<(), String>) {
+    match result {
+        Ok(_) => log!("Reloaded the TLS certificate."),
+        Err(err) => log!("Keeping the old TLS certificate, reloading failed: {err}")
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use rand::Rng;
+use rand::rngs::ThreadRng;
+use serde::{Deserialize, Serialize};
+
+/// What a user is allowed to do.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Full access to the console and every action.
+    Admin,
+    // May only start the server from a sleeping state.
+    Starter
+}
+
+impl Role {
+    /// Whether this role covers everything `needed` may do.
+    pub fn allows(self, needed: Role) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Starter => needed == Role::Starter
+        }
+    }
+}
+
+/// Someone who is logged in, either by password or by client certificate.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub user: String,
+    // What they may do on every server, unless server_roles says otherwise.
+    pub role: Option<Role>,
+    pub server_roles: HashMap<String, Role>
+}
+
+impl Session {
+    /// Someone with the same role on every server.
+    pub fn everywhere(user: &str, role: Role) -> Self {
+        Self { user: user.to_string(), role: Some(role), server_roles: HashMap::new() }
+    }
+
+    /// What they may do on one server, if anything.
+    pub fn role_on(&self, server: &str) -> Option<Role> {
+        self.server_roles.get(server).copied().or(self.role)
+    }
+
+    /// Whether their role on `server` covers everything `needed` may do.
+    pub fn allows(&self, server: &str, needed: Role) -> bool {
+        self.role_on(server).map(|role| role.allows(needed)).unwrap_or(false)
+    }
+}
+
+/// Every login cookie we've handed out and who it belongs to.
+pub struct Sessions {
+    // Generate a random number as a session value.
+    session_gen: ThreadRng,
+    sessions: HashMap<String, Session>
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sessions {
+    pub fn new() -> Self {
+        Self {
+            session_gen: rand::thread_rng(),
+            sessions: HashMap::new()
+        }
+    }
+
+    /// Creates a session and returns the token for its login cookie.
+    pub fn login(&mut self, session: Session) -> String {
+        // Generate a unique login token
+        let login_token = self.session_gen.gen::<u32>().to_string();
+        self.sessions.insert(login_token.clone(), session);
+
+        login_token
+    }
+
+    pub fn get(&self, token: &str) -> Option<&Session> {
+        self.sessions.get(token)
+    }
+
+    pub fn logout(&mut self, token: &str) {
+        self.sessions.remove(token);
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use crate::io_handler::ConsoleLine;
+
+/// The console lines kept in memory, capped by both line count and total size.
+/// Lines are shared behind Arcs, so taking a snapshot never copies any text.
+pub struct Backlog {
+    lines: VecDeque<Arc<ConsoleLine>>,
+    bytes: usize,
+
+    pub max_lines: usize,
+    pub max_bytes: usize,
+    // Longer lines are cut off when they are read, see `truncation_marker`.
+    pub max_line_length: usize
+}
+
+impl Backlog {
+    pub fn new(max_lines: usize, max_bytes: usize, max_line_length: usize) -> Self {
+        Self {
+            lines: VecDeque::with_capacity(max_lines.min(4096)),
+            bytes: 0,
+
+            max_lines,
+            max_bytes,
+            // A limit of zero would read nothing and look like the end of the stream.
+            max_line_length: max_line_length.max(1)
+        }
+    }
+
+    /// Adds a line, dropping the oldest ones until both caps are met again.
+    pub fn push(&mut self, line: ConsoleLine) -> Arc<ConsoleLine> {
+        let line = Arc::new(line);
+        self.bytes += line.size();
+        self.lines.push_back(line.clone());
+
+        // Always keep the newest line, even if it's bigger than the cap on its own.
+        while self.lines.len() > 1 && (self.lines.len() > self.max_lines || self.bytes > self.max_bytes) {
+            if let Some(old) = self.lines.pop_front() {
+                self.bytes -= old.size();
+            }
+        }
+
+        line
+    }
+
+    /// A copy of the current lines that readers can keep while new lines come in.
+    pub fn snapshot(&self) -> Vec<Arc<ConsoleLine>> {
+        self.lines.iter().cloned().collect()
+    }
+
+    pub fn last(&self) -> Option<&Arc<ConsoleLine>> {
+        self.lines.back()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Roughly how much memory the lines take up.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+}
+
+/// What gets put at the end of a line that was cut off.
+pub fn truncation_marker(dropped: usize) -> String {
+    format!(" [... {dropped} more bytes cut off]")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Local;
+    use crate::io_handler::OutputSource;
+    use super::*;
+
+    fn line(text: &str) -> ConsoleLine {
+        ConsoleLine { source: OutputSource::Stdout, time: Local::now(), text: text.to_string(), spans: vec![], log: None, level: None, continuation: false }
+    }
+
+    fn texts(backlog: &Backlog) -> Vec<String> {
+        backlog.snapshot().iter().map(|line| line.text.clone()).collect()
+    }
+
+    #[test]
+    fn caps_the_line_count() {
+        let mut backlog = Backlog::new(3, 1000, 100);
+        for text in ["a", "b", "c", "d", "e"] {
+            backlog.push(line(text));
+        }
+        assert_eq!(texts(&backlog), vec!["c", "d", "e"]);
+        assert_eq!(backlog.bytes(), 3);
+    }
+
+    #[test]
+    fn caps_the_bytes() {
+        let mut backlog = Backlog::new(100, 10, 100);
+        for text in ["1234", "5678", "abcd"] {
+            backlog.push(line(text));
+        }
+        assert_eq!(texts(&backlog), vec!["5678", "abcd"]);
+        assert_eq!(backlog.bytes(), 8);
+    }
+
+    #[test]
+    fn keeps_the_newest_line_even_when_too_big() {
+        let mut backlog = Backlog::new(100, 10, 100);
+        backlog.push(line("small"));
+        backlog.push(line("much too big for the cap"));
+        assert_eq!(texts(&backlog), vec!["much too big for the cap"]);
+        assert_eq!(backlog.len(), 1);
+    }
+
+    #[test]
+    fn snapshots_stay_as_they_were() {
+        let mut backlog = Backlog::new(2, 1000, 100);
+        backlog.push(line("a"));
+        let snapshot = backlog.snapshot();
+        backlog.push(line("b"));
+        backlog.push(line("c"));
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot[0].text, "a");
+    }
+}
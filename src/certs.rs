@@ -0,0 +1,108 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::net::IpAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName};
+use openssl::x509::{X509, X509NameBuilder};
+use crate::log_file::log;
+
+/// Makes a self-signed certificate and key if neither exists yet.
+/// Does nothing when they're already there, so a real certificate is never touched,
+/// and fails when only one of them is, since that's most likely a mistake in the paths.
+pub fn ensure_self_signed(cert_path: &str, key_path: &str, common_name: &str, alt_names: &[String]) -> Result<(), String> {
+    match (Path::new(cert_path).exists(), Path::new(key_path).exists()) {
+        (true, true) => return Ok(()),
+        (true, false) => return Err(format!("There is a certificate at {cert_path} but no key at {key_path}. \
+            Put its key there, or remove the certificate to have a new self-signed pair made")),
+        (false, true) => return Err(format!("There is a key at {key_path} but no certificate at {cert_path}. \
+            Put its certificate there, or remove the key to have a new self-signed pair made")),
+        (false, false) => {}
+    }
+
+    log!("No certificate found at {cert_path}, generating a self-signed one for {common_name}.");
+
+    let (cert, key) = generate_self_signed(common_name, alt_names).map_err(|err| err.to_string())?;
+
+    write_private(key_path, &key)?;
+    write_private(cert_path, &cert.to_pem().map_err(|err| err.to_string())?)?;
+
+    log!("Certificate SHA-256 fingerprint: {}", fingerprint(&cert).map_err(|err| err.to_string())?);
+    log!("Check that your browser shows the same fingerprint the first time you connect.");
+
+    Ok(())
+}
+
+/// Builds a ten year self-signed certificate, returning it with its PEM encoded private key.
+fn generate_self_signed(common_name: &str, alt_names: &[String]) -> Result<(X509, Vec<u8>), openssl::error::ErrorStack> {
+    let rsa = Rsa::generate(2048)?;
+    let key = PKey::from_rsa(rsa)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_text("CN", common_name)?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(159, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    let serial = serial.to_asn1_integer()?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(3650)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    builder.append_extension(KeyUsage::new().critical().digital_signature().key_encipherment().build()?)?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+
+    // Browsers only look at the SANs, so the common name always goes in there too.
+    let mut san = SubjectAlternativeName::new();
+    let mut add_name = |alt_name: &str| {
+        if alt_name.parse::<IpAddr>().is_ok() {
+            san.ip(alt_name);
+        } else {
+            san.dns(alt_name);
+        }
+    };
+    add_name(common_name);
+    for alt_name in alt_names.iter().filter(|alt_name| *alt_name != common_name) {
+        add_name(alt_name);
+    }
+    let san = san.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((builder.build(), key.private_key_to_pem_pkcs8()?))
+}
+
+/// The colon separated SHA-256 fingerprint browsers show for a certificate.
+pub fn fingerprint(cert: &X509) -> Result<String, openssl::error::ErrorStack> {
+    let digest = cert.digest(MessageDigest::sha256())?;
+
+    Ok(digest.iter().map(|byte| format!("{byte:02X}")).collect::<Vec<_>>().join(":"))
+}
+
+/// Writes a file only the handler's user can read.
+fn write_private(path: &str, contents: &[u8]) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(|err| format!("Failed to create {path}: {err}"))?;
+
+    file.write_all(contents).map_err(|err| format!("Failed to write {path}: {err}"))
+}
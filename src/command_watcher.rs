@@ -2,16 +2,35 @@ use std::io::Write;
 use std::process::{Child, ExitStatus};
 
 pub struct CommandWatcher {
-    command: Child
+    command: Child,
+
+    // Where commands are written, the child's stdin or the PTY it runs in.
+    input: Box<dyn Write + Send>
 }
 
 impl CommandWatcher {
-    pub fn new(command: Child) -> Self {
+    pub fn new(mut command: Child) -> Self {
+        let input = Box::new(command.stdin.take().unwrap());
+
         Self {
-            command
+            command,
+            input
         }
     }
 
+    /// Watches a command whose input doesn't go through its own stdin pipe, like one in a PTY.
+    pub fn with_input(command: Child, input: Box<dyn Write + Send>) -> Self {
+        Self {
+            command,
+            input
+        }
+    }
+
+    /// The process id of the child.
+    pub fn pid(&self) -> u32 {
+        self.command.id()
+    }
+
     pub fn check_complete(&mut self) -> Option<ExitStatus> {
         if let Ok(code) = self.command.try_wait() {
             return code;
@@ -25,9 +44,9 @@ impl CommandWatcher {
             return Ok(());
         }
 
-        self.command.stdin.as_mut().unwrap().write_all(write.as_bytes())?;
-        self.command.stdin.as_mut().unwrap().flush().unwrap();
+        self.input.write_all(write.as_bytes())?;
+        self.input.flush()?;
 
-        return Ok(())
+        Ok(())
     }
-}
\ No newline at end of file
+}
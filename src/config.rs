@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::auth::Role;
+use crate::game_events::GameEventPatterns;
+
+/// How the panel is served.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    // Only https on host_ip.
+    #[default]
+    Tls,
+    // Only plain http on host_ip, for use behind a reverse proxy or on localhost.
+    Plain,
+    // https on host_ip and plain http on plain_host_ip.
+    Both
+}
+
+/// Whether https clients are asked for a certificate.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientCertMode {
+    // Never ask for one.
+    #[default]
+    Off,
+    // Ask, but let clients without one log in by password.
+    Optional,
+    // Refuse the connection without a valid certificate.
+    Required
+}
+
+/// A certificate served to clients asking for one of its hostnames.
+#[derive(Deserialize, Clone)]
+pub struct CertificateConfig {
+    pub hostnames: Vec<String>,
+    pub cert_path: String,
+    pub key_path: String
+}
+
+/// Maps the common name of a client certificate to a handler user.
+#[derive(Deserialize, Clone)]
+pub struct ClientCertificate {
+    pub common_name: String,
+    pub user: String,
+    // What they may do on every server, unless server_roles says otherwise.
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub server_roles: HashMap<String, Role>
+}
+
+/// Someone else who can log in with a password, on some or all of the servers.
+#[derive(Deserialize, Clone)]
+pub struct UserConfig {
+    pub name: String,
+    pub pass: String,
+    pub role: Option<Role>,
+    #[serde(default)]
+    pub server_roles: HashMap<String, Role>
+}
+
+/// Everything about one Minecraft server the handler runs.
+#[derive(Deserialize, Clone)]
+pub struct ServerConfig {
+    // Shown in the server switcher and used in links, so only letters, digits, - and _.
+    #[serde(default = "default_server_name")]
+    pub name: String,
+
+    // The old way to start the server, a script run as is. `program` and `args` take over from it.
+    pub run_path: Option<String>,
+    pub program: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    // Where the server runs, and where its files are.
+    #[serde(default = "default_working_dir")]
+    pub working_dir: String,
+    pub env_file: Option<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    #[serde(default = "default_max_lines_shown")]
+    pub max_lines_shown: usize,
+    #[serde(default = "default_max_bytes_shown")]
+    pub max_bytes_shown: usize,
+    #[serde(default = "default_max_line_length")]
+    pub max_line_length: usize,
+    #[serde(default)]
+    pub strip_formatting_in_log: bool,
+
+    #[serde(default = "default_server_logs_dir")]
+    pub server_logs_dir: String,
+
+    #[serde(default)]
+    pub pty: bool,
+    #[serde(default = "default_pty_columns")]
+    pub pty_columns: u16,
+    #[serde(default = "default_pty_rows")]
+    pub pty_rows: u16,
+
+    #[serde(default = "default_player_history_path")]
+    pub player_history_path: String,
+    #[serde(default = "default_player_list_interval")]
+    pub player_list_interval: u64,
+    #[serde(default = "default_audit_log_path")]
+    pub audit_log_path: String,
+
+    // Sent every tps_interval seconds to sample tick times, like "tps", "forge tps" or "tick query".
+    pub tps_command: Option<String>,
+    #[serde(default = "default_tps_interval")]
+    pub tps_interval: u64,
+    #[serde(default = "default_tps_history")]
+    pub tps_history: usize,
+    // A lag alert is raised once TPS has been below tps_alert_below for tps_alert_after seconds.
+    #[serde(default = "default_tps_alert_below")]
+    pub tps_alert_below: f64,
+    #[serde(default = "default_tps_alert_after")]
+    pub tps_alert_after: u64,
+
+    #[serde(default)]
+    pub game_event_patterns: GameEventPatterns
+}
+
+impl ServerConfig {
+    /// The directory the server runs in.
+    pub fn server_dir(&self) -> &Path {
+        Path::new(&self.working_dir)
+    }
+
+    /// A path from the server's point of view, so relative ones start at `working_dir`.
+    pub fn server_path(&self, path: &str) -> PathBuf {
+        self.server_dir().join(path)
+    }
+}
+
+#[derive(Deserialize)]
+pub struct Config {
+    pub main_user: String,
+    pub main_pass: String,
+    pub start_user: String,
+    pub start_pass: String,
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
+    pub host_ip: String,
+    pub receive_threads: u32,
+
+    // The settings of a lone server, written at the top of the file like before there were [[servers]].
+    #[serde(flatten)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub servers: Vec<ServerConfig>,
+
+    #[serde(default = "default_true")]
+    pub log_to_file: bool,
+    #[serde(default = "default_log_dir")]
+    pub log_dir: String,
+    #[serde(default = "default_log_max_bytes")]
+    pub log_max_bytes: u64,
+    #[serde(default = "default_true")]
+    pub log_rotate_daily: bool,
+    #[serde(default = "default_true")]
+    pub log_compress: bool,
+    #[serde(default = "default_log_keep_files")]
+    pub log_keep_files: usize,
+
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+
+    #[serde(default = "default_metrics_interval")]
+    pub metrics_interval: u64,
+    #[serde(default = "default_metrics_history")]
+    pub metrics_history: usize,
+    // Needed as a bearer token to read /metrics, which is open to anyone without one.
+    pub metrics_token: Option<String>,
+
+    #[serde(default)]
+    pub tls_mode: TlsMode,
+    #[serde(default = "default_cert_path")]
+    pub cert_path: String,
+    #[serde(default = "default_key_path")]
+    pub key_path: String,
+    #[serde(default)]
+    pub certificates: Vec<CertificateConfig>,
+    #[serde(default = "default_true")]
+    pub watch_certificate: bool,
+    #[serde(default = "default_true")]
+    pub generate_certificate: bool,
+    #[serde(default = "default_certificate_name")]
+    pub certificate_name: String,
+    #[serde(default = "default_certificate_alt_names")]
+    pub certificate_alt_names: Vec<String>,
+    #[serde(default)]
+    pub client_cert_mode: ClientCertMode,
+    pub client_ca_path: Option<String>,
+    #[serde(default)]
+    pub client_certificates: Vec<ClientCertificate>,
+    #[serde(default = "default_true")]
+    pub password_login: bool,
+    pub plain_host_ip: Option<String>,
+    pub redirect_host_ip: Option<String>
+}
+
+impl Config {
+    /// Every server to run, in the order they were written.
+    /// Without any [[servers]] that's the one set up at the top of the file.
+    pub fn server_configs(&self) -> Result<Vec<ServerConfig>, String> {
+        if self.servers.is_empty() {
+            return Ok(vec![self.server.clone()]);
+        }
+        if self.server.program.is_some() || self.server.run_path.is_some() {
+            return Err("program and run_path go in each [[servers]] entry once there are any!".to_string());
+        }
+
+        let mut servers = self.servers.clone();
+        for (index, server) in servers.iter_mut().enumerate() {
+            let valid = !server.name.is_empty() && server.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(format!("The server name {:?} may only have letters, digits, - and _!", server.name));
+            }
+            if self.servers[..index].iter().any(|other| other.name == server.name) {
+                return Err(format!("There are two servers called {}!", server.name));
+            }
+
+            // Every server keeps its own history, unless told to share one.
+            if server.player_history_path == default_player_history_path() {
+                server.player_history_path = format!("handler-players-{}.json", server.name);
+            }
+            if server.audit_log_path == default_audit_log_path() {
+                server.audit_log_path = format!("handler-audit-{}.jsonl", server.name);
+            }
+        }
+
+        Ok(servers)
+    }
+}
+
+fn default_request_timeout() -> u64 { 10 }
+fn default_max_bytes_shown() -> usize { 4 * 1024 * 1024 }
+fn default_max_line_length() -> usize { 8 * 1024 }
+fn default_server_name() -> String { "server".to_string() }
+fn default_max_lines_shown() -> usize { 200 }
+fn default_working_dir() -> String { ".".to_string() }
+fn default_server_logs_dir() -> String { "logs".to_string() }
+fn default_log_dir() -> String { "handler-logs".to_string() }
+fn default_log_max_bytes() -> u64 { 10 * 1024 * 1024 }
+fn default_log_keep_files() -> usize { 30 }
+fn default_pty_columns() -> u16 { 160 }
+fn default_pty_rows() -> u16 { 48 }
+fn default_cert_path() -> String { "cert.pem".to_string() }
+fn default_key_path() -> String { "key.pem".to_string() }
+fn default_player_history_path() -> String { "handler-players.json".to_string() }
+fn default_player_list_interval() -> u64 { 60 }
+fn default_audit_log_path() -> String { "handler-audit.jsonl".to_string() }
+fn default_tps_interval() -> u64 { 30 }
+fn default_tps_history() -> usize { 240 }
+fn default_tps_alert_below() -> f64 { 18.0 }
+fn default_tps_alert_after() -> u64 { 120 }
+fn default_metrics_interval() -> u64 { 5 }
+fn default_metrics_history() -> usize { 720 }
+fn default_true() -> bool { true }
+fn default_certificate_name() -> String { "localhost".to_string() }
+fn default_certificate_alt_names() -> Vec<String> { vec!["localhost".to_string(), "127.0.0.1".to_string()] }
@@ -0,0 +1,47 @@
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use crate::io_handler::OutputSource;
+use crate::server::ServerStream;
+
+/// Everything the main loop can be woken up by.
+/// Every producer (web workers, stdin, the child's output) sends into one channel,
+/// so the main loop can block on it instead of spinning.
+pub enum Event {
+    // A fully parsed request from one of the web workers.
+    Request(ServerStream),
+
+    // A line typed into the handler's own console.
+    Stdin(String),
+
+    // A line printed by one of the servers, by its index, on stdout or stderr.
+    Output(usize, OutputSource, String),
+
+    // A server closed one of its output streams, so it is most likely exiting.
+    OutputClosed(usize),
+
+    // Someone sent SIGHUP, so the certificate should be loaded again.
+    ReloadTls,
+
+    // Another tick went by, used for periodic checks.
+    Tick
+}
+
+/// Blocks until the next event comes in, or returns a Tick once `next_tick` passes and moves it `tick` ahead.
+/// Ticks keep coming however busy the channel is, so a chatty console can't hold off the periodic checks.
+/// Returns None when every sender is gone and no more events can arrive.
+pub fn next_event(receiver: &Receiver<Event>, next_tick: &mut Instant, tick: Duration) -> Option<Event> {
+    let now = Instant::now();
+    if now >= *next_tick {
+        *next_tick = now + tick;
+        return Some(Event::Tick);
+    }
+
+    match receiver.recv_timeout(*next_tick - now) {
+        Ok(event) => Some(event),
+        Err(RecvTimeoutError::Timeout) => {
+            *next_tick = Instant::now() + tick;
+            Some(Event::Tick)
+        }
+        Err(RecvTimeoutError::Disconnected) => None
+    }
+}
@@ -0,0 +1,298 @@
+use serde::Serialize;
+
+/// How a piece of console text should look.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Style {
+    // A css color like #55FF55, or None for the default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub italic: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub underline: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub strikethrough: bool,
+    #[serde(skip_serializing_if = "is_false")]
+    pub obfuscated: bool
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// A run of text that all has the same style.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub text: String,
+    #[serde(flatten)]
+    pub style: Style
+}
+
+// The colors behind §0 to §f.
+const MINECRAFT_COLORS: [&str; 16] = [
+    "#000000", "#0000AA", "#00AA00", "#00AAAA", "#AA0000", "#AA00AA", "#FFAA00", "#AAAAAA",
+    "#555555", "#5555FF", "#55FF55", "#55FFFF", "#FF5555", "#FF55FF", "#FFFF55", "#FFFFFF"
+];
+
+// The colors behind ANSI 30-37 and 90-97, in the same order as the 256 color palette starts.
+const ANSI_COLORS: [&str; 16] = [
+    "#000000", "#AA0000", "#00AA00", "#AA5500", "#0000AA", "#AA00AA", "#00AAAA", "#AAAAAA",
+    "#555555", "#FF5555", "#55FF55", "#FFFF55", "#5555FF", "#FF55FF", "#55FFFF", "#FFFFFF"
+];
+
+/// Splits a line into styled spans, reading both ANSI SGR sequences and § formatting codes.
+/// Any other escape sequence is dropped.
+pub fn parse(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = vec![];
+    let mut style = Style::default();
+    let mut current = String::new();
+
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        let new_style = match c {
+            '\x1b' if chars.get(i + 1) == Some(&'[') => {
+                // Read up to the final byte of the CSI sequence.
+                let start = i + 2;
+                let mut end = start;
+                while end < chars.len() && !('@'..='~').contains(&chars[end]) {
+                    end += 1;
+                }
+                i = end + 1;
+
+                // Only SGR changes the style, everything else is just dropped.
+                if chars.get(end) == Some(&'m') {
+                    let params: String = chars[start..end].iter().collect();
+                    Some(apply_sgr(&style, &params))
+                } else {
+                    continue;
+                }
+            }
+            '\x1b' => {
+                // Some other two character escape, skip it.
+                i += 2;
+                continue;
+            }
+            '§' => match (chars.get(i + 1), hex_code(&chars[i..])) {
+                // §x§R§R§G§G§B§B is how Bukkit writes hex colors.
+                (_, Some(color)) => {
+                    i += 14;
+                    Some(Style { color: Some(color), ..Style::default() })
+                }
+                (Some(&code), None) => {
+                    i += 2;
+                    match apply_section_code(&style, code) {
+                        Some(new_style) => Some(new_style),
+                        // Not a formatting code, so it was just text.
+                        None => {
+                            current.push('§');
+                            current.push(code);
+                            continue;
+                        }
+                    }
+                }
+                (None, None) => {
+                    i += 1;
+                    current.push('§');
+                    continue;
+                }
+            },
+            c => {
+                i += 1;
+                current.push(c);
+                continue;
+            }
+        };
+
+        if let Some(new_style) = new_style {
+            if new_style != style {
+                push_span(&mut spans, &mut current, &style);
+                style = new_style;
+            }
+        }
+    }
+
+    push_span(&mut spans, &mut current, &style);
+
+    spans
+}
+
+fn push_span(spans: &mut Vec<Span>, current: &mut String, style: &Style) {
+    if current.is_empty() {
+        return;
+    }
+
+    spans.push(Span { text: std::mem::take(current), style: style.clone() });
+}
+
+/// Applies a `§` code, or returns None if it isn't one.
+fn apply_section_code(style: &Style, code: char) -> Option<Style> {
+    let code = code.to_ascii_lowercase();
+
+    // Colors reset every other format, like they do in game.
+    if let Some(index) = code.to_digit(16) {
+        return Some(Style { color: Some(MINECRAFT_COLORS[index as usize].to_string()), ..Style::default() });
+    }
+
+    let mut style = style.clone();
+    match code {
+        'k' => style.obfuscated = true,
+        'l' => style.bold = true,
+        'm' => style.strikethrough = true,
+        'n' => style.underline = true,
+        'o' => style.italic = true,
+        'r' => style = Style::default(),
+        _ => return None
+    }
+
+    Some(style)
+}
+
+/// Reads a `§x§R§R§G§G§B§B` hex color from the start of `chars`.
+fn hex_code(chars: &[char]) -> Option<String> {
+    if chars.len() < 14 || !matches!(chars[1], 'x' | 'X') {
+        return None;
+    }
+
+    let mut color = String::from("#");
+    for pair in chars[2..14].chunks(2) {
+        if pair[0] != '§' || !pair[1].is_ascii_hexdigit() {
+            return None;
+        }
+        color.push(pair[1].to_ascii_uppercase());
+    }
+
+    Some(color)
+}
+
+/// Applies the parameters of an ANSI SGR (`ESC[...m`) sequence.
+fn apply_sgr(style: &Style, params: &str) -> Style {
+    let mut style = style.clone();
+
+    // An empty sequence means reset.
+    let params: Vec<u32> = params.split(';').map(|param| param.parse().unwrap_or(0)).collect();
+    let mut params = params.into_iter();
+
+    while let Some(param) = params.next() {
+        match param {
+            0 => style = Style::default(),
+            1 => style.bold = true,
+            3 => style.italic = true,
+            4 => style.underline = true,
+            5 | 6 => style.obfuscated = true,
+            9 => style.strikethrough = true,
+            22 => style.bold = false,
+            23 => style.italic = false,
+            24 => style.underline = false,
+            25 => style.obfuscated = false,
+            29 => style.strikethrough = false,
+            30..=37 => style.color = Some(ANSI_COLORS[(param - 30) as usize].to_string()),
+            90..=97 => style.color = Some(ANSI_COLORS[(param - 90 + 8) as usize].to_string()),
+            39 => style.color = None,
+            38 | 48 => {
+                // Extended colors, 5;n for the 256 palette or 2;r;g;b for true color.
+                let color = match params.next() {
+                    Some(5) => params.next().map(palette_color),
+                    Some(2) => {
+                        let (r, g, b) = (params.next().unwrap_or(0), params.next().unwrap_or(0), params.next().unwrap_or(0));
+                        Some(format!("#{:02X}{:02X}{:02X}", r.min(255), g.min(255), b.min(255)))
+                    }
+                    _ => None
+                };
+
+                // Backgrounds aren't shown, but their parameters still have to be skipped.
+                if param == 38 {
+                    if let Some(color) = color {
+                        style.color = Some(color);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    style
+}
+
+/// Looks up a color from the xterm 256 color palette.
+fn palette_color(index: u32) -> String {
+    match index {
+        0..=15 => ANSI_COLORS[index as usize].to_string(),
+        16..=231 => {
+            // A 6x6x6 color cube.
+            let index = index - 16;
+            let level = |value: u32| if value == 0 { 0 } else { 55 + value * 40 };
+            format!("#{:02X}{:02X}{:02X}", level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        232..=255 => {
+            let gray = 8 + (index - 232) * 10;
+            format!("#{gray:02X}{gray:02X}{gray:02X}")
+        }
+        _ => ANSI_COLORS[7].to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(text: &str, style: Style) -> Span {
+        Span { text: text.to_string(), style }
+    }
+
+    fn color(color: &str) -> Style {
+        Style { color: Some(color.to_string()), ..Style::default() }
+    }
+
+    #[test]
+    fn sgr_reset() {
+        assert_eq!(parse("\x1b[1;31mred\x1b[0mplain\x1b[32mgreen\x1b[mplain"), vec![
+            span("red", Style { bold: true, ..color("#AA0000") }),
+            span("plain", Style::default()),
+            span("green", color("#00AA00")),
+            span("plain", Style::default())
+        ]);
+    }
+
+    #[test]
+    fn extended_sgr_colors() {
+        assert_eq!(parse("\x1b[38;5;196mpalette\x1b[38;5;244mgray\x1b[38;2;18;52;86mtrue\x1b[48;5;21;4mbackground"), vec![
+            span("palette", color("#FF0000")),
+            span("gray", color("#808080")),
+            span("true", color("#123456")),
+            span("background", Style { underline: true, ..color("#123456") })
+        ]);
+    }
+
+    #[test]
+    fn section_codes() {
+        assert_eq!(parse("§aGreen §lbold§r plain"), vec![
+            span("Green ", color("#55FF55")),
+            span("bold", Style { bold: true, ..color("#55FF55") }),
+            span(" plain", Style::default())
+        ]);
+    }
+
+    #[test]
+    fn section_hex_colors() {
+        assert_eq!(parse("§x§1§2§a§B§c§Dhex"), vec![span("hex", color("#12ABCD"))]);
+    }
+
+    #[test]
+    fn invalid_or_cut_off_section_codes_are_text() {
+        assert_eq!(parse("50§ off§"), vec![span("50§ off§", Style::default())]);
+        assert_eq!(parse("§zword"), vec![span("§zword", Style::default())]);
+        // Too short to be a hex color, so the x is kept and the rest are read one by one.
+        assert_eq!(parse("§x§F§Fend"), vec![span("§x", Style::default()), span("end", color("#FFFFFF"))]);
+    }
+
+    #[test]
+    fn other_escapes_are_dropped() {
+        assert_eq!(parse("\x1b[2K\x1b[1Gtext\x1b7"), vec![span("text", Style::default())]);
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use chrono::{DateTime, Local};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use crate::io_handler::ConsoleLine;
+
+// The vanilla messages, which Paper, Forge and Fabric print the same way.
+// Each pattern is matched against the message of a log line, and has to have the named groups shown.
+const UUID: &str = r"^UUID of player (?<name>\w+) is (?<uuid>[0-9a-fA-F-]{36})";
+const LOGIN: &str = r"^(?<name>\w+)\[/(?<ip>[^\]]+?)(?::\d+)?\] logged in with entity id";
+const JOINED: &str = r"^(?<name>\w+) joined the game";
+const LOST_CONNECTION: &str = r"^(?<name>\w+) lost connection: (?<reason>.*)";
+const LEFT: &str = r"^(?<name>\w+) left the game";
+const CHAT: &str = r"^(?:\[Not Secure\] )?<(?<player>\w+)> (?<message>.*)";
+const DEATH: &str = concat!(
+    r"^(?<player>\w+) (?:was |died|drowned|blew up|fell |hit the ground|burned|went up in flames|went off with a bang|",
+    r"walked into|tried to swim|starved|suffocated|withered away|froze|experienced kinetic energy|",
+    r"discovered the floor|didn't want to live|left the confines|got finished off|suffered)"
+);
+const ADVANCEMENT: &str = r"^(?<player>\w+) has (?:made the advancement|completed the challenge|reached the goal) \[(?<advancement>.*)\]";
+const DONE: &str = r"Done \((?<seconds>[\d.,]+)s\)!";
+const PLAYER_LIST: &str = r"^There are \d+ of a max(?: of)? \d+ players online:(?<names>.*)";
+const CRASH: &str = r"^(?:Encountered an unexpected exception|Considering it to be crashed)";
+const CANT_KEEP_UP: &str = r"^Can't keep up! Is the server overloaded\? Running (?<milliseconds>\d+)ms or (?<ticks>\d+) ticks behind";
+// The answers to the usual tps commands, which need a (?<tps>...) group, a (?<mspt>...) group or both.
+const TICK_TIMES: [&str; 4] = [
+    // Paper's and Spigot's `tps`, which put a * in front of values capped at 20.
+    r"^TPS from last 1m, 5m, 15m: \*?(?<tps>[\d.]+)",
+    // `forge tps` up to 1.18.
+    r"^Overall ?: Mean tick time: (?<mspt>[\d.]+) ms\. Mean TPS: (?<tps>[\d.]+)",
+    // `forge tps` and `neoforge tps` since.
+    r"^Overall: (?<tps>[\d.]+) TPS \((?<mspt>[\d.]+) ms/tick\)",
+    // Vanilla's `tick query`, which puts this on a line of its own without a log prefix.
+    r"^Average time per tick: (?<mspt>[\d.]+)ms"
+];
+
+/// Something that happened in the game, read from the console.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    PlayerJoined { name: String, uuid: Option<String>, ip: Option<String> },
+    PlayerLeft { name: String, reason: Option<String> },
+    Chat { player: String, message: String },
+    Death { player: String, message: String },
+    Advancement { player: String, advancement: String },
+    // How long the server said it took to load, in seconds.
+    ServerDone { startup_time: Option<f64> },
+    // The answer to a `list` command.
+    PlayerList { names: Vec<String> },
+    Crash { message: String },
+    // The answer to tps_command.
+    TickTimes { tps: Option<f64>, mspt: Option<f64> },
+    // How far behind the server said it was.
+    CantKeepUp { milliseconds: u64, ticks: u64 },
+    // TPS has been below tps_alert_below for `seconds`, or without tps_command, "Can't keep up!" warnings kept coming.
+    LagAlert { tps: Option<f64>, seconds: u64 },
+    LagRecovered { tps: Option<f64> }
+}
+
+/// Replacements for the patterns that recognize events, for modded servers that word things differently.
+/// Every pattern needs the same named groups as the one it replaces.
+#[derive(Deserialize, Clone, Default)]
+pub struct GameEventPatterns {
+    pub uuid: Option<String>,
+    pub login: Option<String>,
+    pub joined: Option<String>,
+    pub lost_connection: Option<String>,
+    pub left: Option<String>,
+    pub chat: Option<String>,
+    pub death: Option<String>,
+    pub advancement: Option<String>,
+    pub done: Option<String>,
+    pub player_list: Option<String>,
+    pub crash: Option<String>,
+    pub cant_keep_up: Option<String>,
+    // Replaces all of the built in ones.
+    pub tick_times: Option<String>
+}
+
+/// Turns console lines into game events.
+/// Joins and leaves are spread over several lines, so it remembers what it has seen of each player.
+pub struct GameEventRecognizer {
+    uuid: Regex,
+    login: Regex,
+    joined: Regex,
+    lost_connection: Regex,
+    left: Regex,
+    chat: Regex,
+    death: Regex,
+    advancement: Regex,
+    done: Regex,
+    player_list: Regex,
+    crash: Regex,
+    cant_keep_up: Regex,
+    tick_times: Vec<Regex>,
+
+    // Read from the lines before "joined the game" and "left the game".
+    uuids: HashMap<String, String>,
+    ips: HashMap<String, String>,
+    reasons: HashMap<String, String>,
+
+    // Deaths are only believed for players that are online, since they look like any other message.
+    online: HashSet<String>
+}
+
+impl GameEventRecognizer {
+    pub fn new(patterns: &GameEventPatterns) -> Result<Self, String> {
+        let compile = |name: &str, custom: &Option<String>, default: &str, groups: &[&str]| -> Result<Regex, String> {
+            let regex = Regex::new(custom.as_deref().unwrap_or(default))
+                .map_err(|err| format!("Invalid {name} pattern: {err}"))?;
+            for group in groups {
+                if !regex.capture_names().any(|capture| capture == Some(group)) {
+                    return Err(format!("The {name} pattern needs a (?<{group}>...) group"));
+                }
+            }
+            Ok(regex)
+        };
+
+        let tick_times = match &patterns.tick_times {
+            Some(pattern) => vec![pattern.as_str()],
+            None => TICK_TIMES.to_vec()
+        };
+        let tick_times = tick_times.into_iter().map(|pattern| {
+            let regex = Regex::new(pattern).map_err(|err| format!("Invalid tick_times pattern: {err}"))?;
+            if !regex.capture_names().any(|capture| matches!(capture, Some("tps" | "mspt"))) {
+                return Err("The tick_times pattern needs a (?<tps>...) or (?<mspt>...) group".to_string());
+            }
+            Ok(regex)
+        }).collect::<Result<Vec<Regex>, String>>()?;
+
+        Ok(Self {
+            uuid: compile("uuid", &patterns.uuid, UUID, &["name", "uuid"])?,
+            login: compile("login", &patterns.login, LOGIN, &["name", "ip"])?,
+            joined: compile("joined", &patterns.joined, JOINED, &["name"])?,
+            lost_connection: compile("lost_connection", &patterns.lost_connection, LOST_CONNECTION, &["name", "reason"])?,
+            left: compile("left", &patterns.left, LEFT, &["name"])?,
+            chat: compile("chat", &patterns.chat, CHAT, &["player", "message"])?,
+            death: compile("death", &patterns.death, DEATH, &["player"])?,
+            advancement: compile("advancement", &patterns.advancement, ADVANCEMENT, &["player", "advancement"])?,
+            done: compile("done", &patterns.done, DONE, &["seconds"])?,
+            player_list: compile("player_list", &patterns.player_list, PLAYER_LIST, &["names"])?,
+            crash: compile("crash", &patterns.crash, CRASH, &[])?,
+            cant_keep_up: compile("cant_keep_up", &patterns.cant_keep_up, CANT_KEEP_UP, &["milliseconds", "ticks"])?,
+            tick_times,
+
+            uuids: HashMap::new(),
+            ips: HashMap::new(),
+            reasons: HashMap::new(),
+
+            online: HashSet::new()
+        })
+    }
+
+    /// Forgets every player, for when the server starts again.
+    pub fn reset(&mut self) {
+        self.uuids.clear();
+        self.ips.clear();
+        self.reasons.clear();
+        self.online.clear();
+    }
+
+    /// The event a line stands for, if any.
+    pub fn recognize(&mut self, line: &ConsoleLine) -> Option<GameEvent> {
+        let message = line.log.as_ref().map(|log| log.message.as_str()).unwrap_or(&line.text);
+        let group = |captures: &regex::Captures, name: &str| captures.name(name).map(|value| value.as_str().to_string()).unwrap_or_default();
+        let number = |captures: &regex::Captures, name: &str| captures.name(name).and_then(|value| value.as_str().parse::<f64>().ok());
+
+        // Checked before continuations, since some answers take more than one line.
+        if let Some(captures) = self.tick_times.iter().find_map(|regex| regex.captures(message)) {
+            return Some(GameEvent::TickTimes { tps: number(&captures, "tps"), mspt: number(&captures, "mspt") });
+        }
+        // Stack traces never hold events, and would be mistaken for deaths.
+        if line.continuation {
+            return None;
+        }
+
+        if let Some(captures) = self.uuid.captures(message) {
+            self.uuids.insert(group(&captures, "name"), group(&captures, "uuid"));
+            return None;
+        }
+        if let Some(captures) = self.login.captures(message) {
+            self.ips.insert(group(&captures, "name"), group(&captures, "ip"));
+            return None;
+        }
+        if let Some(captures) = self.lost_connection.captures(message) {
+            self.reasons.insert(group(&captures, "name"), group(&captures, "reason"));
+            return None;
+        }
+
+        if let Some(captures) = self.chat.captures(message) {
+            return Some(GameEvent::Chat { player: group(&captures, "player"), message: group(&captures, "message") });
+        }
+        if let Some(captures) = self.joined.captures(message) {
+            let name = group(&captures, "name");
+            self.online.insert(name.clone());
+            return Some(GameEvent::PlayerJoined {
+                uuid: self.uuids.remove(&name),
+                ip: self.ips.remove(&name),
+                name
+            });
+        }
+        if let Some(captures) = self.left.captures(message) {
+            let name = group(&captures, "name");
+            self.online.remove(&name);
+            return Some(GameEvent::PlayerLeft { reason: self.reasons.remove(&name), name });
+        }
+        if let Some(captures) = self.advancement.captures(message) {
+            return Some(GameEvent::Advancement { player: group(&captures, "player"), advancement: group(&captures, "advancement") });
+        }
+        if let Some(captures) = self.death.captures(message) {
+            let player = group(&captures, "player");
+            if self.online.contains(&player) {
+                return Some(GameEvent::Death { player, message: message.to_string() });
+            }
+        }
+        if let Some(captures) = self.done.captures(message) {
+            let startup_time = captures.name("seconds").and_then(|seconds| seconds.as_str().replace(',', ".").parse().ok());
+            return Some(GameEvent::ServerDone { startup_time });
+        }
+        if let Some(captures) = self.player_list.captures(message) {
+            let names = group(&captures, "names").split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string).collect();
+            return Some(GameEvent::PlayerList { names });
+        }
+        if let Some(captures) = self.cant_keep_up.captures(message) {
+            return Some(GameEvent::CantKeepUp {
+                milliseconds: group(&captures, "milliseconds").parse().unwrap_or(0),
+                ticks: group(&captures, "ticks").parse().unwrap_or(0)
+            });
+        }
+        if self.crash.is_match(message) {
+            return Some(GameEvent::Crash { message: message.to_string() });
+        }
+
+        None
+    }
+}
+
+/// An event along with when it happened.
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordedEvent {
+    pub time: DateTime<Local>,
+    #[serde(flatten)]
+    pub event: GameEvent
+}
+
+pub type Subscriber = Box<dyn FnMut(&RecordedEvent) + Send>;
+
+/// Hands every game event to whoever subscribed, and keeps the latest ones for the UI.
+pub struct EventBus {
+    subscribers: Vec<Subscriber>,
+    recent: VecDeque<RecordedEvent>,
+    max_recent: usize
+}
+
+impl EventBus {
+    pub fn new(max_recent: usize) -> Self {
+        Self {
+            subscribers: vec![],
+            recent: VecDeque::new(),
+            max_recent
+        }
+    }
+
+    /// Registers a function that is called with every event from now on.
+    pub fn subscribe(&mut self, subscriber: Subscriber) {
+        self.subscribers.push(subscriber);
+    }
+
+    pub fn publish(&mut self, event: GameEvent) {
+        let recorded = RecordedEvent { time: Local::now(), event };
+
+        for subscriber in &mut self.subscribers {
+            subscriber(&recorded);
+        }
+
+        self.recent.push_back(recorded);
+        while self.recent.len() > self.max_recent {
+            self.recent.pop_front();
+        }
+    }
+
+    /// The latest events, oldest first.
+    pub fn recent(&self) -> Vec<RecordedEvent> {
+        self.recent.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io_handler::OutputSource;
+    use crate::log_parser;
+    use super::*;
+
+    fn line(text: &str) -> ConsoleLine {
+        let log = log_parser::parse(text);
+        ConsoleLine {
+            source: OutputSource::Stdout,
+            time: Local::now(),
+            text: text.to_string(),
+            spans: vec![],
+            level: log.as_ref().map(|log| log.level),
+            log,
+            continuation: false
+        }
+    }
+
+    fn recognizer() -> GameEventRecognizer {
+        GameEventRecognizer::new(&GameEventPatterns::default()).unwrap()
+    }
+
+    #[test]
+    fn join_with_uuid_and_ip() {
+        let mut recognizer = recognizer();
+        assert_eq!(recognizer.recognize(&line("[12:00:00] [User Authenticator #1/INFO]: UUID of player Notch is 069a79f4-44e9-4726-a5be-fca90e38aaf5")), None);
+        assert_eq!(recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: Notch[/127.0.0.1:51234] logged in with entity id 42 at (0.5, 64.0, 0.5)")), None);
+        assert_eq!(recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: Notch joined the game")), Some(GameEvent::PlayerJoined {
+            name: "Notch".to_string(),
+            uuid: Some("069a79f4-44e9-4726-a5be-fca90e38aaf5".to_string()),
+            ip: Some("127.0.0.1".to_string())
+        }));
+    }
+
+    #[test]
+    fn leave_with_reason() {
+        let mut recognizer = recognizer();
+        recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: Notch joined the game"));
+        assert_eq!(recognizer.recognize(&line("[12:05:00] [Server thread/INFO]: Notch lost connection: Disconnected")), None);
+        assert_eq!(recognizer.recognize(&line("[12:05:00] [Server thread/INFO]: Notch left the game")), Some(GameEvent::PlayerLeft {
+            name: "Notch".to_string(),
+            reason: Some("Disconnected".to_string())
+        }));
+    }
+
+    #[test]
+    fn deaths_only_count_while_online() {
+        let mut recognizer = recognizer();
+        let death = "[12:01:00] [Server thread/INFO]: Notch was slain by Zombie";
+        assert_eq!(recognizer.recognize(&line(death)), None);
+
+        recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: Notch joined the game"));
+        assert_eq!(recognizer.recognize(&line(death)), Some(GameEvent::Death {
+            player: "Notch".to_string(),
+            message: "Notch was slain by Zombie".to_string()
+        }));
+
+        recognizer.recognize(&line("[12:02:00] [Server thread/INFO]: Notch left the game"));
+        assert_eq!(recognizer.recognize(&line(death)), None);
+    }
+
+    #[test]
+    fn chat_is_not_a_join() {
+        let mut recognizer = recognizer();
+        assert_eq!(recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: <Steve> Notch joined the game")), Some(GameEvent::Chat {
+            player: "Steve".to_string(),
+            message: "Notch joined the game".to_string()
+        }));
+        assert_eq!(recognizer.recognize(&line("[12:01:00] [Server thread/INFO]: Notch was slain by Zombie")), None);
+    }
+
+    #[test]
+    fn player_list() {
+        let mut recognizer = recognizer();
+        assert_eq!(recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: There are 2 of a max of 20 players online: Notch, Steve")),
+                   Some(GameEvent::PlayerList { names: vec!["Notch".to_string(), "Steve".to_string()] }));
+        assert_eq!(recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: There are 0 of a max of 20 players online:")),
+                   Some(GameEvent::PlayerList { names: vec![] }));
+    }
+
+    #[test]
+    fn custom_patterns_need_their_groups() {
+        let patterns = GameEventPatterns { joined: Some(r"^(?<player>\w+) joined".to_string()), ..Default::default() };
+        assert_eq!(GameEventRecognizer::new(&patterns).err().as_deref(), Some("The joined pattern needs a (?<name>...) group"));
+
+        let patterns = GameEventPatterns { tick_times: Some(r"^TPS: [\d.]+".to_string()), ..Default::default() };
+        assert!(GameEventRecognizer::new(&patterns).is_err());
+
+        let patterns = GameEventPatterns { joined: Some(r"^(?<name>\w+) has arrived".to_string()), ..Default::default() };
+        let mut recognizer = GameEventRecognizer::new(&patterns).unwrap();
+        assert!(matches!(recognizer.recognize(&line("[12:00:00] [Server thread/INFO]: Notch has arrived")), Some(GameEvent::PlayerJoined { .. })));
+    }
+}
@@ -0,0 +1,420 @@
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+use chrono::Local;
+use crate::access_lists;
+use crate::backlog::Backlog;
+use crate::command_watcher::CommandWatcher;
+use crate::config::ServerConfig;
+use crate::event::Event;
+use crate::game_events::{EventBus, GameEvent, GameEventRecognizer};
+use crate::io_handler::{ConsoleLine, OutputSource, ServerIOHandler};
+use crate::launch;
+use crate::lifecycle::{Lifecycle, LifecycleState};
+use crate::log_file::log;
+use crate::metrics::Metrics;
+use crate::moderation::{self, Action, AuditEntry, AuditLog};
+use crate::players::Players;
+use crate::pty::Pty;
+use crate::server::ServerStream;
+use crate::tps::TickMonitor;
+
+// How many game events are kept for /api/events.
+const RECENT_EVENTS: usize = 200;
+// How long after the process exits its output may stay open, in case something it started still holds it.
+const OUTPUT_GRACE: Duration = Duration::from_secs(5);
+
+/// The child process along with everything that watches it.
+pub struct RunningServer {
+    pub command_watcher: CommandWatcher,
+    pub stdio_handler: ServerIOHandler,
+    // Output streams that haven't closed yet, so nothing they still have queued is lost.
+    open_streams: usize,
+    // When the process was first seen to have exited.
+    exited_at: Option<Instant>
+}
+
+/// One Minecraft server, with its process and everything the handler keeps track of for it.
+pub struct Instance {
+    // Where it is in the config, which is how its output finds its way back here.
+    pub index: usize,
+    pub config: ServerConfig,
+    // Put in front of log messages when there's more than one server.
+    label: Option<String>,
+    pub lifecycle: Lifecycle,
+
+    // Reads joins, chat and the like from the console, and tells whoever subscribed.
+    recognizer: GameEventRecognizer,
+    pub game_events: EventBus,
+    // Kept up to date by its subscription to game_events.
+    pub players: Arc<Mutex<Players>>,
+    // Every kick, ban and the like done from the players page.
+    pub audit: AuditLog,
+    // Set when server.properties was saved while the server was up, until it starts again.
+    pub properties_need_restart: bool,
+    // When `list` was last sent, to keep the online players right.
+    last_player_list: Instant,
+    // CPU, memory and the like of the process and everything it started.
+    pub metrics: Metrics,
+    // TPS, MSPT and lag warnings, along with the lag alert.
+    pub ticks: TickMonitor,
+    // When tps_command was last sent.
+    last_tps_request: Instant,
+
+    // The process, only there while the lifecycle says there is one.
+    pub running: Option<RunningServer>,
+    // When it was spawned.
+    started: Option<Instant>,
+    // What the last process printed, kept once it exits so the console can show why.
+    last_output: Vec<Arc<ConsoleLine>>,
+
+    // Handed to the output threads of every process we spawn.
+    events: Sender<Event>
+}
+
+impl Instance {
+    pub fn new(index: usize, config: ServerConfig, labelled: bool, metrics: Metrics, events: Sender<Event>) -> Self {
+        let label = labelled.then(|| config.name.clone());
+        let prefix = prefix(&label);
+        let lag_prefix = prefix.clone();
+
+        let mut lifecycle = Lifecycle::new();
+        lifecycle.subscribe(Box::new(move |transition| {
+            log!("{prefix}Server state changed from {} to {}", transition.from, transition.to);
+        }));
+
+        let recognizer = GameEventRecognizer::new(&config.game_event_patterns)
+            .unwrap_or_else(|err| panic!("{err} in the game_event_patterns of {}!", config.name));
+        let players = Arc::new(Mutex::new(Players::load(PathBuf::from(&config.player_history_path))));
+        // Players and the lag log follow the game through the bus.
+        let mut game_events = EventBus::new(RECENT_EVENTS);
+        let subscribed = players.clone();
+        game_events.subscribe(Box::new(move |recorded| subscribed.lock().unwrap().handle_event(&recorded.event)));
+        let alert_below = config.tps_alert_below;
+        game_events.subscribe(Box::new(move |recorded| match recorded.event {
+            GameEvent::LagAlert { tps: Some(tps), seconds } => log!("{lag_prefix}TPS has been below {alert_below} for {seconds}s, it's at {tps:.1} now"),
+            GameEvent::LagAlert { tps: None, seconds } => log!("{lag_prefix}The server has been saying it can't keep up for {seconds}s"),
+            GameEvent::LagRecovered { tps: Some(tps) } => log!("{lag_prefix}TPS is back up to {tps:.1}"),
+            GameEvent::LagRecovered { tps: None } => log!("{lag_prefix}The server has stopped saying it can't keep up"),
+            _ => {}
+        }));
+
+        let audit = AuditLog::new(PathBuf::from(&config.audit_log_path));
+        // Without tps_command there are no samples, so the warnings are all there is to go by.
+        let ticks = TickMonitor::new(config.tps_alert_below, Duration::from_secs(config.tps_alert_after), config.tps_history,
+                                     config.tps_command.is_none());
+
+        Self {
+            index,
+            config,
+            label,
+            lifecycle,
+
+            recognizer,
+            game_events,
+            players,
+            audit,
+            properties_need_restart: false,
+            last_player_list: Instant::now(),
+            metrics,
+            ticks,
+            last_tps_request: Instant::now(),
+
+            running: None,
+            started: None,
+            last_output: vec![],
+
+            events
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Spawns the server process, if the lifecycle allows it.
+    pub fn start(&mut self) {
+        if let Err(err) = self.lifecycle.transition(LifecycleState::Starting) {
+            log!("{}{err}", prefix(&self.label));
+            return;
+        }
+        self.recognizer.reset();
+        self.properties_need_restart = false;
+        self.metrics.reset();
+        self.ticks.reset();
+
+        let mut command = match launch::server_command(&self.config) {
+            Ok(command) => command,
+            Err(err) => {
+                log!("{}Failed to start the server: {err}", prefix(&self.label));
+                let _ = self.lifecycle.transition(LifecycleState::Crashed);
+                return;
+            }
+        };
+
+        let spawned = if self.config.pty {
+            spawn_in_pty(&mut command, self.config.pty_columns, self.config.pty_rows)
+        } else {
+            spawn_piped(&mut command)
+        };
+
+        let (command_watcher, streams) = match spawned {
+            Ok(spawned) => spawned,
+            Err(err) => {
+                log!("{}Failed to start the server: {err}", prefix(&self.label));
+                let _ = self.lifecycle.transition(LifecycleState::Crashed);
+                return;
+            }
+        };
+
+        // Build our STDIO Handler that will handle the console output.
+        let backlog = Backlog::new(self.config.max_lines_shown, self.config.max_bytes_shown, self.config.max_line_length);
+        let open_streams = streams.len();
+        let stdio_handler = ServerIOHandler::new(self.index, self.label.clone(), streams, backlog,
+                                                 self.config.strip_formatting_in_log, self.events.clone());
+
+        self.running = Some(RunningServer { command_watcher, stdio_handler, open_streams, exited_at: None });
+        self.last_output.clear();
+        self.started = Some(Instant::now());
+    }
+
+    /// How long the process has been up, if there is one.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.started.map(|started| started.elapsed())
+    }
+
+    /// Asks the server to stop, if the lifecycle allows it.
+    pub fn stop(&mut self) {
+        if !self.lifecycle.state().can_stop() {
+            return;
+        }
+
+        self.send_command("stop".to_string());
+
+        let _ = self.lifecycle.transition(LifecycleState::Stopping);
+    }
+
+    /// Sends a line to the server's stdin, if there is a server.
+    pub fn send_command(&mut self, command: String) {
+        if let Some(running) = &mut self.running {
+            if let Err(err) = running.command_watcher.send_string(command) {
+                log!("{}Sending input to command failed with result {err:?}", prefix(&self.label))
+            }
+        }
+    }
+
+    /// Stores a line of output, and acts on any game event in it.
+    pub fn handle_output(&mut self, source: OutputSource, output: String) {
+        // This will print the console of the command to our stdout.
+        let line = match &mut self.running {
+            Some(running) => running.stdio_handler.handle_output(source, output),
+            None => return
+        };
+
+        if let Some(event) = self.recognizer.recognize(&line) {
+            self.publish_game_event(event);
+        }
+    }
+
+    /// What the console shows right now.
+    pub fn lines(&self) -> Vec<Arc<ConsoleLine>> {
+        match &self.running {
+            Some(running) => running.stdio_handler.lines.snapshot(),
+            None => self.last_output.clone()
+        }
+    }
+
+    /// Kicks, bans, ops or whitelists someone from the players page.
+    /// This goes through the console while the server runs, and straight into the files while it's offline.
+    pub fn moderate(&mut self, request: &ServerStream, user: &str) -> Result<String, String> {
+        let action = request.get_content("action").and_then(|action| Action::parse(&action)).ok_or("Unknown action")?;
+        let target = request.get_content("target").unwrap_or_default().trim().to_string();
+        moderation::check_target(action, &target)?;
+        let reason = moderation::clean_reason(&request.get_content("reason").unwrap_or_default());
+        // Online mode servers can't work out the UUID of someone who never joined, so it can be given.
+        let uuid = match request.get_content("uuid").filter(|uuid| !uuid.trim().is_empty()) {
+            Some(uuid) => Some(access_lists::parse_uuid(&uuid)?),
+            None => None
+        };
+
+        let state = self.lifecycle.state();
+        let applied_by = match state {
+            LifecycleState::Running => {
+                self.send_command(action.command(&target, &reason));
+                "command"
+            }
+            // The server keeps the lists in memory and would write over any changes.
+            _ if state.has_process() => return Err(format!("The server is {state}, try again once it's running or offline")),
+            _ => {
+                let known_uuid = uuid.or_else(|| self.players.lock().unwrap().uuid_of(&target));
+                moderation::apply_offline(self.config.server_dir(), action, &target, known_uuid, &reason, user)?;
+                "file"
+            }
+        };
+
+        log!("{}{user} used {} on {target}{}", prefix(&self.label), action.name(), if reason.is_empty() { String::new() } else { format!(": {reason}") });
+        let entry = AuditEntry {
+            time: Local::now(),
+            user: user.to_string(),
+            action,
+            target: target.clone(),
+            reason,
+            applied_by: applied_by.to_string()
+        };
+        if let Err(err) = self.audit.record(&entry) {
+            log!("{err}");
+        }
+
+        Ok(format!("Done: {} {target}", action.name()))
+    }
+
+    /// Samples the process tree, if it's time to.
+    pub fn sample_metrics(&mut self) {
+        if let Some(running) = &self.running {
+            self.metrics.sample_if_due(running.command_watcher.pid());
+        }
+    }
+
+    /// Sends `list` every player_list_interval seconds, in case a join or leave line was missed.
+    pub fn request_player_list(&mut self) {
+        let interval = self.config.player_list_interval;
+        if interval == 0 || self.lifecycle.state() != LifecycleState::Running || self.last_player_list.elapsed().as_secs() < interval {
+            return;
+        }
+
+        self.last_player_list = Instant::now();
+        self.send_command("list".to_string());
+    }
+
+    /// Sends tps_command every tps_interval seconds, if there is one.
+    pub fn request_tps(&mut self) {
+        let command = match &self.config.tps_command {
+            Some(command) => command.clone(),
+            None => return
+        };
+        let interval = self.config.tps_interval;
+        if interval == 0 || self.lifecycle.state() != LifecycleState::Running || self.last_tps_request.elapsed().as_secs() < interval {
+            return;
+        }
+
+        self.last_tps_request = Instant::now();
+        self.send_command(command);
+    }
+
+    /// Publishes the end of a lag alert that warnings raised, once they've stopped.
+    pub fn check_lag(&mut self) {
+        if let Some(event) = self.ticks.check() {
+            self.publish_game_event(event);
+        }
+    }
+
+    fn publish_game_event(&mut self, event: GameEvent) {
+        // The tick monitor is called directly rather than subscribed, since it publishes alerts of its own.
+        match event {
+            // Not published, since one every tps_interval would crowd everything else out of the recent events.
+            GameEvent::TickTimes { tps, mspt } => {
+                if let Some(alert) = self.ticks.record(tps, mspt) {
+                    self.publish_game_event(alert);
+                }
+                return;
+            }
+            GameEvent::CantKeepUp { milliseconds, ticks } => {
+                if let Some(alert) = self.ticks.warn(milliseconds, ticks) {
+                    self.game_events.publish(event.clone());
+                    self.publish_game_event(alert);
+                    return;
+                }
+            }
+            _ => {}
+        }
+
+        // Vanilla and all of its forks print this once the world is loaded.
+        if matches!(event, GameEvent::ServerDone { .. }) && self.lifecycle.state() == LifecycleState::Starting {
+            let _ = self.lifecycle.transition(LifecycleState::Running);
+        }
+
+        self.game_events.publish(event);
+    }
+
+    /// Notes that one of the output streams closed, and checks on the process once they all have.
+    pub fn output_closed(&mut self) -> bool {
+        match &mut self.running {
+            Some(running) => running.open_streams = running.open_streams.saturating_sub(1),
+            None => return false
+        }
+        self.check_exited()
+    }
+
+    /// Checks if the process has exited, and moves the lifecycle along if it has.
+    /// Returns whether it did.
+    pub fn check_exited(&mut self) -> bool {
+        let code = match &mut self.running {
+            Some(running) => match running.command_watcher.check_complete() {
+                // Lines can still be on their way from the streams, so wait for them to close.
+                Some(_) if running.open_streams > 0 && running.exited_at.get_or_insert_with(Instant::now).elapsed() < OUTPUT_GRACE => return false,
+                Some(code) => code,
+                None => return false
+            },
+            None => return false
+        };
+
+        log!("{}Command exited with code {code}", prefix(&self.label));
+        if let Some(running) = self.running.take() {
+            self.last_output = running.stdio_handler.lines.snapshot();
+        }
+        self.started = None;
+        self.players.lock().unwrap().leave_all();
+
+        // Anything but a requested stop or a clean exit counts as a crash.
+        let next = if self.lifecycle.state() == LifecycleState::Stopping || code.success() {
+            LifecycleState::Offline
+        } else {
+            LifecycleState::Crashed
+        };
+
+        if let Err(err) = self.lifecycle.transition(next) {
+            log!("{}{err}", prefix(&self.label));
+        }
+
+        // Sets one run apart from the next, in the log file too.
+        log!("{}-------------------------------------", prefix(&self.label));
+
+        true
+    }
+}
+
+/// What goes in front of a log message about this server.
+fn prefix(label: &Option<String>) -> String {
+    label.as_ref().map(|label| format!("[{label}] ")).unwrap_or_default()
+}
+
+/// The watcher for a freshly spawned child, and every stream its output comes from.
+type Spawned = (CommandWatcher, Vec<(Box<dyn Read + Send>, OutputSource)>);
+
+/// Spawns the command with its stdin, stdout and stderr piped to us.
+fn spawn_piped(command: &mut Command) -> std::io::Result<Spawned> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let streams: Vec<(Box<dyn Read + Send>, OutputSource)> = vec![
+        (Box::new(child.stdout.take().unwrap()), OutputSource::Stdout),
+        (Box::new(child.stderr.take().unwrap()), OutputSource::Stderr)
+    ];
+
+    Ok((CommandWatcher::new(child), streams))
+}
+
+/// Spawns the command inside a PTY, where stdout and stderr end up as one stream.
+fn spawn_in_pty(command: &mut Command, columns: u16, rows: u16) -> std::io::Result<Spawned> {
+    let (child, master) = Pty::open(columns, rows)?.spawn(command)?;
+
+    let streams: Vec<(Box<dyn Read + Send>, OutputSource)> = vec![(Box::new(master.try_clone()?), OutputSource::Stdout)];
+
+    Ok((CommandWatcher::with_input(child, Box::new(master)), streams))
+}
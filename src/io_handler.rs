@@ -1,105 +1,264 @@
-use std::io::{BufRead, BufReader};
-use std::process::{ChildStdout};
-use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
+use std::io::{BufRead, BufReader, Read};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use std::{io, thread};
 use std::thread::JoinHandle;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use crate::backlog::{truncation_marker, Backlog};
+use crate::event::Event;
+use crate::formatting::{self, Span};
+use crate::log_file::{self, log};
+use crate::log_parser::{self, Level, LogLine};
+use crate::pty::clean_line;
 
-pub struct ServerIOHandler {
-    // This is the value that will be sent as the console to the server.
-    pub total_string: Vec<String>,
+/// Which of the child's streams a line came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputSource {
+    Stdout,
+    Stderr
+}
 
-    // This catches the console input from the server machine
-    pub stdin_thread: JoinHandle<()>,
-    pub stdin_receiver: Receiver<String>,
+impl OutputSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputSource::Stdout => "stdout",
+            OutputSource::Stderr => "stderr"
+        }
+    }
+}
 
-    // This catches the console output from the child process
-    pub stdout_thread: JoinHandle<()>,
-    pub stdout_receiver: Receiver<String>,
+/// A single line of console output.
+#[derive(Clone, Debug, Serialize)]
+pub struct ConsoleLine {
+    pub source: OutputSource,
+    // When the line was printed.
+    #[serde(skip)]
+    pub time: DateTime<Local>,
+    // The text without any color or formatting codes.
+    pub text: String,
+    // The same text, split up by how it should look.
+    pub spans: Vec<Span>,
+    // The parts of the line, if it's in one of the usual log formats.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<LogLine>,
+    // The line's level, or the level of the entry it continues.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<Level>,
+    // Whether it's part of the entry before it, like a line of a stack trace.
+    #[serde(skip_serializing_if = "is_false")]
+    pub continuation: bool
+}
 
-    // The maximum number of lines that the server will store and show from the underlying process.
-    pub max_lines: usize
+fn is_false(value: &bool) -> bool {
+    !value
 }
 
-impl ServerIOHandler {
-    pub fn new(stdout: ChildStdout, max_lines: usize) -> Self {
-        let (in_send, in_receive) = mpsc::channel();
-        let (out_send, out_receive) = mpsc::channel();
+impl ConsoleLine {
+    /// Roughly how many bytes the line takes up in memory.
+    pub fn size(&self) -> usize {
+        self.text.len()
+            + self.spans.iter().map(|span| span.text.len() + span.style.color.as_ref().map(String::len).unwrap_or(0)).sum::<usize>()
+            + self.log.as_ref().map(LogLine::size).unwrap_or(0)
+    }
+}
+
+pub struct ServerIOHandler {
+    // These are the lines that will be sent as the console to the server.
+    pub lines: Backlog,
+
+    // These catch the console output from the child process, one per stream.
+    pub output_threads: Vec<JoinHandle<()>>,
+
+    // Whether colors are taken out of what we print.
+    pub strip_formatting: bool,
 
-        let stdin_thread = thread::spawn(move || input_catcher(in_send));
+    // Put in front of what we print when there's more than one server, so their output can be told apart.
+    pub label: Option<String>
+}
 
-        let stdout_thread = thread::spawn(move || output_catcher(out_send, stdout));
+impl ServerIOHandler {
+    /// Catches output from every stream on its own thread, tagging lines with where they came from.
+    pub fn new(server: usize, label: Option<String>, streams: Vec<(Box<dyn Read + Send>, OutputSource)>, lines: Backlog,
+               strip_formatting: bool, events: Sender<Event>) -> Self {
+        let max_line_length = lines.max_line_length;
+        let output_threads = streams.into_iter().map(|(stream, source)| {
+            let events = events.clone();
+            thread::spawn(move || output_catcher(events, server, stream, source, max_line_length))
+        }).collect();
 
         Self {
-            total_string: vec![],
+            lines,
 
-            stdin_thread,
-            stdin_receiver: in_receive,
+            output_threads,
 
-            stdout_thread,
-            stdout_receiver: out_receive,
+            strip_formatting,
 
-            max_lines
+            label
         }
     }
 
-    pub fn handle_output(&mut self) {
-        while let Ok(receive) = self.stdout_receiver.try_recv() {
-            print!("{receive}");
-            self.total_string.push(receive);
+    /// Prints and stores a line from the child, handing back what was stored.
+    pub fn handle_output(&mut self, source: OutputSource, receive: String) -> Arc<ConsoleLine> {
+        // Terminals (and a child running in a PTY) can send cursor movement along with the text.
+        let raw = clean_line(receive.trim_end_matches(['\r', '\n']));
+        let spans = formatting::parse(&raw);
+        let text: String = spans.iter().map(|span| span.text.as_str()).collect();
 
-            while self.total_string.len() > self.max_lines {
-                self.total_string.remove(0);
-            }
-        }
-    }
+        let printed = if self.strip_formatting { &text } else { &raw };
 
-    pub fn handle_input(&mut self) -> Vec<String> {
-        let mut result = vec![];
-        // Handle The Input
-        while let Ok(receive) = self.stdin_receiver.try_recv() {
-            println!("Received {receive}");
-            result.push(receive);
+        // Keep the streams apart on our side too.
+        let prefix = self.label.as_ref().map(|label| format!("[{label}] ")).unwrap_or_default();
+        match source {
+            OutputSource::Stdout => println!("{prefix}{printed}"),
+            OutputSource::Stderr => eprintln!("{prefix}{printed}")
+        }
+        match &self.label {
+            Some(label) => log_file::write(&format!("{label}/{}", source.name()), printed),
+            None => log_file::write(source.name(), printed)
         }
 
-        result
+        let log = log_parser::parse(&text);
+        let (level, continuation) = match (&log, self.lines.last()) {
+            (Some(log), _) => (Some(log.level), false),
+            // Stack traces and the like are shown and filtered along with the entry they belong to.
+            (None, Some(last)) if last.source == source && log_parser::is_continuation(&text) => (last.level, true),
+            (None, _) => (None, false)
+        };
+
+        self.lines.push(ConsoleLine { source, time: Local::now(), text, spans, log, level, continuation })
     }
 }
 
-pub fn output_catcher(msg_link: Sender<String>, stdout: ChildStdout) {
-    let mut reader = BufReader::new(stdout);
+pub fn output_catcher(msg_link: Sender<Event>, server: usize, stream: impl Read, source: OutputSource, max_line_length: usize) {
+    let mut reader = BufReader::new(stream);
 
     loop {
-        let mut output = String::new();
-        match reader.read_line(&mut output) {
-            Ok(_) => { }
+        let output = match read_limited_line(&mut reader, max_line_length) {
+            Ok(Some(output)) => output,
+            // EOF, the child has closed its end.
+            Ok(None) => { break; }
             Err(_) => { break; }
-        }
+        };
 
-        match msg_link.send(output) {
+        match msg_link.send(Event::Output(server, source, output)) {
             Ok(_) => {},
             Err(_) => { break; }
         }
     }
 
-    println!("Output catcher done.")
+    // Let the main loop know it should check on the child.
+    let _ = msg_link.send(Event::OutputClosed(server));
+
+    log!("Output catcher done.")
+}
+
+/// Reads a line of at most `limit` bytes. Anything past that is skipped and replaced with a marker,
+/// so a runaway line can never use more memory than that.
+/// Bytes that aren't valid UTF-8 are replaced instead of ending the stream.
+fn read_limited_line(reader: &mut impl BufRead, limit: usize) -> io::Result<Option<String>> {
+    let mut line = vec![];
+    let read = reader.by_ref().take(limit as u64).read_until(b'\n', &mut line)?;
+    if read == 0 {
+        return Ok(None);
+    }
+
+    let mut output = String::from_utf8_lossy(&line).to_string();
+    if line.ends_with(b"\n") || read < limit {
+        return Ok(Some(output));
+    }
+
+    // Too long, throw away the rest of the line.
+    let mut dropped = 0;
+    let mut last = None;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+
+        match buffer.iter().position(|&byte| byte == b'\n') {
+            Some(end) => {
+                dropped += end;
+                last = buffer[..end].last().copied().or(last);
+                reader.consume(end + 1);
+                // A \r\n ending isn't part of the line either.
+                if last == Some(b'\r') {
+                    dropped -= 1;
+                }
+                break;
+            }
+            None => {
+                let length = buffer.len();
+                dropped += length;
+                last = buffer.last().copied();
+                reader.consume(length);
+            }
+        }
+    }
+
+    // A line of exactly `limit` bytes lost nothing but its line ending.
+    if dropped > 0 {
+        output.push_str(&truncation_marker(dropped));
+    }
+    Ok(Some(output))
 }
 
-pub fn input_catcher(msg_link: Sender<String>) {
+pub fn input_catcher(msg_link: Sender<Event>) {
     let mut reader = BufReader::new(io::stdin());
 
     loop {
         let mut output = String::new();
         match reader.read_line(&mut output) {
+            // Stdin was closed, nothing more will come in.
+            Ok(0) => { break; }
             Ok(_) => {}
             Err(_) => { break; }
         }
 
-        match msg_link.send(output) {
+        match msg_link.send(Event::Stdin(output)) {
             Ok(_) => {}
             Err(_) => { break; }
         }
     }
 
-    println!("Input catcher done.")
-}
\ No newline at end of file
+    log!("Input catcher done.")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    fn lines(input: &str, limit: usize) -> Vec<String> {
+        // A tiny buffer, so long lines are thrown away over several reads.
+        let mut reader = BufReader::with_capacity(4, Cursor::new(input.as_bytes().to_vec()));
+        std::iter::from_fn(|| read_limited_line(&mut reader, limit).unwrap()).collect()
+    }
+
+    #[test]
+    fn short_lines_are_kept_whole() {
+        assert_eq!(lines("one\ntwo\nlast", 8), vec!["one\n", "two\n", "last"]);
+    }
+
+    #[test]
+    fn lines_of_exactly_the_limit_are_not_marked() {
+        assert_eq!(lines("12345\nnext\n", 5), vec!["12345", "next\n"]);
+        assert_eq!(lines("12345\r\nnext\n", 5), vec!["12345", "next\n"]);
+        assert_eq!(lines("12345", 5), vec!["12345"]);
+    }
+
+    #[test]
+    fn long_lines_are_cut_off() {
+        assert_eq!(lines("1234567890abc\nnext\n", 5), vec![format!("12345{}", truncation_marker(8)), "next\n".to_string()]);
+        assert_eq!(lines("1234567\r\n", 5), vec![format!("12345{}", truncation_marker(2))]);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let mut reader = BufReader::new(Cursor::new(b"ok \xff\n".to_vec()));
+        assert_eq!(read_limited_line(&mut reader, 100).unwrap().as_deref(), Some("ok \u{fffd}\n"));
+        assert_eq!(read_limited_line(&mut reader, 100).unwrap(), None);
+    }
+}
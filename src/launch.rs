@@ -0,0 +1,153 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use crate::config::ServerConfig;
+
+/// Checks the launch settings once at startup, so a typo doesn't wait until the first start to show up.
+pub fn check(config: &ServerConfig) -> Result<(), String> {
+    match (&config.program, &config.run_path) {
+        (None, None) => return Err(format!("Please set program (or run_path) for {} in mcserver-handler.toml!", config.name)),
+        (Some(_), Some(_)) => return Err(format!("Only one of program and run_path can be set for {}!", config.name)),
+        _ => {}
+    }
+    if !config.server_dir().is_dir() {
+        return Err(format!("working_dir {} of {} isn't a directory!", config.working_dir, config.name));
+    }
+
+    Ok(())
+}
+
+/// The command that starts the server, run in `working_dir` with the configured environment.
+/// The env file is read every time, so changes to it only need a restart of the server.
+/// Like the program, a relative env file starts at `working_dir`.
+pub fn server_command(config: &ServerConfig) -> Result<Command, String> {
+    let program = config.program.as_ref().or(config.run_path.as_ref()).ok_or("Nothing to run")?;
+
+    let mut command = Command::new(program_path(config.server_dir(), program));
+    command.args(&config.args).current_dir(config.server_dir());
+
+    if let Some(env_file) = &config.env_file {
+        command.envs(read_env_file(&config.server_path(env_file))?);
+    }
+    // The ones in the config win over the file.
+    command.envs(&config.env);
+
+    Ok(command)
+}
+
+/// Paths like ./run.sh start at the working directory, bare names like java are looked up in PATH.
+fn program_path(dir: &Path, program: &str) -> String {
+    if program.contains('/') {
+        dir.join(program).to_string_lossy().into_owned()
+    } else {
+        program.to_string()
+    }
+}
+
+/// Reads KEY=VALUE lines, skipping blank lines and # comments.
+/// Values may be quoted and lines may start with `export`, so files written for a shell work too.
+pub fn read_env_file(path: &Path) -> Result<Vec<(String, String)>, String> {
+    let contents = fs::read_to_string(path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    let mut vars = vec![];
+
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line).trim_start();
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => (key.trim(), value.trim()),
+            _ => return Err(format!("Line {} of {} isn't KEY=VALUE", number + 1, path.display()))
+        };
+        let value = ['"', '\''].iter()
+            .find_map(|&quote| value.strip_prefix(quote).and_then(|value| value.strip_suffix(quote)))
+            .unwrap_or(value);
+
+        vars.push((key.to_string(), value.to_string()));
+    }
+
+    Ok(vars)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("mcserver-handler-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn reads_env_files() {
+        let dir = temp_dir("env");
+        let path = dir.join(".env");
+        fs::write(&path, concat!(
+            "# Memory for the JVM\n",
+            "\n",
+            "MEMORY=4G\n",
+            "   \n",
+            "export JAVA_HOME=\"/usr/lib/jvm/java-21\"\n",
+            "GREETING='hello world'\n",
+            "EMPTY=\n",
+            "  SPACED = value with spaces  \n",
+            "EQUALS=a=b\n"
+        )).unwrap();
+
+        assert_eq!(read_env_file(&path).unwrap(), vars(&[
+            ("MEMORY", "4G"),
+            ("JAVA_HOME", "/usr/lib/jvm/java-21"),
+            ("GREETING", "hello world"),
+            ("EMPTY", ""),
+            ("SPACED", "value with spaces"),
+            ("EQUALS", "a=b")
+        ]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_lines_without_an_equals_sign() {
+        let dir = temp_dir("env-bad");
+        let path = dir.join(".env");
+
+        fs::write(&path, "GOOD=1\nJUST_A_NAME\n").unwrap();
+        let err = read_env_file(&path).unwrap_err();
+        assert!(err.starts_with("Line 2 of "), "{err}");
+
+        fs::write(&path, "=value\n").unwrap();
+        assert!(read_env_file(&path).is_err());
+
+        assert!(read_env_file(&dir.join("missing.env")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn env_files_start_at_the_working_dir() {
+        let dir = temp_dir("env-dir");
+        fs::write(dir.join("server.env"), "FROM_FILE=1\nBOTH=file\n").unwrap();
+
+        let config: ServerConfig = toml::from_str(&format!(
+            "program = \"java\"\nworking_dir = {:?}\nenv_file = \"server.env\"\n[env]\nBOTH = \"config\"\n",
+            dir.to_string_lossy()
+        )).unwrap();
+        let command = server_command(&config).unwrap();
+        let envs: Vec<(String, String)> = command.get_envs()
+            .map(|(key, value)| (key.to_string_lossy().into_owned(), value.unwrap().to_string_lossy().into_owned()))
+            .collect();
+        assert_eq!(envs, vars(&[("BOTH", "config"), ("FROM_FILE", "1")]));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
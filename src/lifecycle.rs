@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use serde::Serialize;
+
+/// Every state the managed Minecraft server can be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleState {
+    // No process, waiting for someone to start it.
+    Offline,
+    // The process was spawned but hasn't finished loading the world yet.
+    Starting,
+    // The server is up and accepting players.
+    Running,
+    // A stop was sent and we're waiting for the process to exit.
+    Stopping,
+    // The process exited without being asked to.
+    Crashed,
+    // The world files are being copied, so nothing may start the server.
+    // Reserved for backups, nothing enters it yet.
+    BackingUp
+}
+
+impl LifecycleState {
+    pub const ALL: [LifecycleState; 6] = [
+        LifecycleState::Offline, LifecycleState::Starting, LifecycleState::Running,
+        LifecycleState::Stopping, LifecycleState::Crashed, LifecycleState::BackingUp
+    ];
+
+    /// Whether moving from this state straight to `to` is allowed.
+    pub fn can_transition_to(self, to: LifecycleState) -> bool {
+        use LifecycleState::*;
+
+        matches!((self, to),
+            (Offline, Starting) | (Offline, BackingUp)
+            | (Starting, Running) | (Starting, Stopping) | (Starting, Offline) | (Starting, Crashed)
+            | (Running, Stopping) | (Running, Offline) | (Running, Crashed)
+            | (Stopping, Offline)
+            | (Crashed, Starting) | (Crashed, Offline) | (Crashed, BackingUp)
+            | (BackingUp, Offline)
+        )
+    }
+
+    /// Whether there is a child process attached in this state.
+    pub fn has_process(self) -> bool {
+        matches!(self, LifecycleState::Starting | LifecycleState::Running | LifecycleState::Stopping)
+    }
+
+    /// Whether someone may start the server from this state.
+    pub fn can_start(self) -> bool {
+        self.can_transition_to(LifecycleState::Starting)
+    }
+
+    /// Whether someone may ask the server to stop from this state.
+    pub fn can_stop(self) -> bool {
+        self.can_transition_to(LifecycleState::Stopping)
+    }
+
+    /// The lowercase name shown to users.
+    pub fn name(self) -> &'static str {
+        match self {
+            LifecycleState::Offline => "offline",
+            LifecycleState::Starting => "starting",
+            LifecycleState::Running => "running",
+            LifecycleState::Stopping => "stopping",
+            LifecycleState::Crashed => "crashed",
+            LifecycleState::BackingUp => "backing up"
+        }
+    }
+}
+
+impl Display for LifecycleState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A single change of state, handed to every observer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub from: LifecycleState,
+    pub to: LifecycleState
+}
+
+/// Returned when a transition isn't allowed from the current state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TransitionError {
+    pub from: LifecycleState,
+    pub to: LifecycleState
+}
+
+impl Display for TransitionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The server can't go from {} to {}", self.from, self.to)
+    }
+}
+
+pub type Observer = Box<dyn FnMut(&Transition) + Send>;
+
+/// Owns the current state of the server and tells everyone who cares when it changes.
+/// This never touches the process itself, so it can be driven by hand.
+pub struct Lifecycle {
+    state: LifecycleState,
+    observers: Vec<Observer>,
+    // How many times each state was entered, for counting restarts and crashes.
+    entered: HashMap<LifecycleState, u64>
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            state: LifecycleState::Offline,
+            observers: vec![],
+            entered: HashMap::new()
+        }
+    }
+
+    pub fn state(&self) -> LifecycleState {
+        self.state
+    }
+
+    pub fn times_entered(&self, state: LifecycleState) -> u64 {
+        self.entered.get(&state).copied().unwrap_or(0)
+    }
+
+    /// Registers a function that is called after every successful transition.
+    pub fn subscribe(&mut self, observer: Observer) {
+        self.observers.push(observer);
+    }
+
+    /// Moves to `to` if that is a valid transition, and notifies the observers.
+    pub fn transition(&mut self, to: LifecycleState) -> Result<Transition, TransitionError> {
+        if !self.state.can_transition_to(to) {
+            return Err(TransitionError { from: self.state, to });
+        }
+
+        let transition = Transition { from: self.state, to };
+        self.state = to;
+        *self.entered.entry(to).or_default() += 1;
+
+        for observer in &mut self.observers {
+            observer(&transition);
+        }
+
+        Ok(transition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use super::*;
+    use LifecycleState::*;
+
+    #[test]
+    fn allowed_transitions_move_the_state() {
+        let mut lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.state(), Offline);
+
+        for to in [Starting, Running, Stopping, Offline, Starting, Crashed, Starting, Offline, BackingUp, Offline] {
+            let from = lifecycle.state();
+            assert_eq!(lifecycle.transition(to), Ok(Transition { from, to }));
+            assert_eq!(lifecycle.state(), to);
+        }
+    }
+
+    #[test]
+    fn refused_transitions_keep_the_state() {
+        let mut lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.transition(Running), Err(TransitionError { from: Offline, to: Running }));
+        assert_eq!(lifecycle.transition(Stopping), Err(TransitionError { from: Offline, to: Stopping }));
+        assert_eq!(lifecycle.state(), Offline);
+
+        lifecycle.transition(Starting).unwrap();
+        lifecycle.transition(Running).unwrap();
+        lifecycle.transition(Stopping).unwrap();
+        let err = lifecycle.transition(Starting).unwrap_err();
+        assert_eq!(err, TransitionError { from: Stopping, to: Starting });
+        assert_eq!(err.to_string(), "The server can't go from stopping to starting");
+        assert_eq!(lifecycle.state(), Stopping);
+    }
+
+    #[test]
+    fn nothing_starts_while_backing_up() {
+        assert!(!BackingUp.can_start());
+        assert!(!BackingUp.can_stop());
+        assert!(!BackingUp.has_process());
+        assert!(!Running.can_transition_to(BackingUp));
+    }
+
+    #[test]
+    fn no_state_moves_to_itself() {
+        for state in LifecycleState::ALL {
+            assert!(!state.can_transition_to(state), "{state} to itself");
+        }
+    }
+
+    #[test]
+    fn observers_only_hear_about_successful_transitions() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let mut lifecycle = Lifecycle::new();
+        let observer_seen = seen.clone();
+        lifecycle.subscribe(Box::new(move |transition| observer_seen.lock().unwrap().push(*transition)));
+
+        lifecycle.transition(Starting).unwrap();
+        assert!(lifecycle.transition(Starting).is_err());
+        lifecycle.transition(Stopping).unwrap();
+        let _ = lifecycle.transition(Running);
+
+        assert_eq!(*seen.lock().unwrap(), vec![
+            Transition { from: Offline, to: Starting },
+            Transition { from: Starting, to: Stopping }
+        ]);
+    }
+
+    #[test]
+    fn counts_how_often_each_state_was_entered() {
+        let mut lifecycle = Lifecycle::new();
+        for to in [Starting, Running, Crashed, Starting, Crashed, Starting, Running, Stopping, Offline] {
+            lifecycle.transition(to).unwrap();
+        }
+        // Refused ones don't count.
+        let _ = lifecycle.transition(Running);
+
+        assert_eq!(lifecycle.times_entered(Starting), 3);
+        assert_eq!(lifecycle.times_entered(Running), 2);
+        assert_eq!(lifecycle.times_entered(Crashed), 2);
+        assert_eq!(lifecycle.times_entered(Stopping), 1);
+        assert_eq!(lifecycle.times_entered(Offline), 1);
+        assert_eq!(lifecycle.times_entered(BackingUp), 0);
+    }
+}
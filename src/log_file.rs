@@ -0,0 +1,200 @@
+use std::cmp::Reverse;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use chrono::{Local, NaiveDate};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use crate::config::Config;
+
+/// The log every thread writes to, set up once by `init`.
+static LOG: Mutex<Option<LogFile>> = Mutex::new(None);
+
+/// Prints a message from the handler itself, and writes it to the log file too.
+macro_rules! log {
+    ($($arg:tt)*) => {{
+        let message = format!($($arg)*);
+        println!("{message}");
+        $crate::log_file::write("handler", &message);
+    }};
+}
+pub(crate) use log;
+
+/// Starts writing the log file, if the config asks for one.
+pub fn init(config: &Config) {
+    if !config.log_to_file {
+        return;
+    }
+
+    match LogFile::open(config) {
+        Ok(log_file) => *LOG.lock().unwrap() = Some(log_file),
+        Err(err) => println!("Failed to open the log file in {}: {err}", config.log_dir)
+    }
+}
+
+/// Writes a timestamped line to the log file, if there is one.
+pub fn write(source: &str, text: &str) {
+    if let Some(log_file) = LOG.lock().unwrap().as_mut() {
+        if let Err(err) = log_file.write(source, text) {
+            println!("Failed to write to the log file: {err}");
+        }
+    }
+}
+
+/// A log file that rolls over by size or by day,
+/// compressing the old files and keeping only the newest few.
+pub struct LogFile {
+    dir: PathBuf,
+    file: BufWriter<File>,
+
+    // How big the current file is, and which day it was started on.
+    size: u64,
+    day: NaiveDate,
+
+    max_bytes: u64,
+    rotate_daily: bool,
+    // Rotated files go to the thread that compresses them and removes old ones.
+    rotated: Sender<PathBuf>
+}
+
+impl LogFile {
+    pub fn open(config: &Config) -> io::Result<Self> {
+        let dir = PathBuf::from(&config.log_dir);
+        fs::create_dir_all(&dir)?;
+
+        let (file, size) = open_current(&dir)?;
+
+        // Pick up where the last run left off, including the day it was started on.
+        let day = fs::metadata(current_path(&dir))
+            .and_then(|meta| meta.modified())
+            .map(|modified| chrono::DateTime::<Local>::from(modified).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+
+        let (rotated, receiver) = mpsc::channel();
+        let (cleanup_dir, compress, keep_files) = (dir.clone(), config.log_compress, config.log_keep_files);
+        thread::spawn(move || clean_up(receiver, &cleanup_dir, compress, keep_files));
+
+        Ok(Self {
+            dir,
+            file,
+
+            size,
+            day,
+
+            max_bytes: config.log_max_bytes,
+            rotate_daily: config.log_rotate_daily,
+            rotated
+        })
+    }
+
+    pub fn write(&mut self, source: &str, text: &str) -> io::Result<()> {
+        let now = Local::now();
+
+        let too_big = self.max_bytes > 0 && self.size >= self.max_bytes;
+        let new_day = self.rotate_daily && now.date_naive() != self.day;
+        if too_big || new_day {
+            self.rotate()?;
+            self.day = now.date_naive();
+        }
+
+        let line = format!("[{}] [{source}] {text}\n", now.format("%Y-%m-%d %H:%M:%S"));
+        self.file.write_all(line.as_bytes())?;
+        // Flush every line, a log that loses its end on a crash isn't worth much.
+        self.file.flush()?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Moves the current file aside and starts a new one.
+    /// Compressing and cleaning up happens on another thread, so logging never waits on gzip.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        // Name the old file after the day it was written, numbered in case there are several.
+        let mut index = 1;
+        let rotated = loop {
+            let name = format!("console-{}-{index}.log", self.day.format("%Y-%m-%d"));
+            let path = self.dir.join(&name);
+            if !path.exists() && !self.dir.join(format!("{name}.gz")).exists() {
+                break path;
+            }
+            index += 1;
+        };
+
+        fs::rename(current_path(&self.dir), &rotated)?;
+        let (file, size) = open_current(&self.dir)?;
+        self.file = file;
+        self.size = size;
+
+        let _ = self.rotated.send(rotated);
+
+        Ok(())
+    }
+}
+
+/// Compresses each rotated file and then removes the old ones, one file at a time,
+/// so a cleanup never runs into a file that is still being compressed.
+fn clean_up(rotated: Receiver<PathBuf>, dir: &Path, compress: bool, keep_files: usize) {
+    for path in rotated {
+        if compress {
+            if let Err(err) = compress_file(&path) {
+                println!("Failed to compress {}: {err}", path.display());
+            }
+        }
+        if let Err(err) = remove_old(dir, keep_files) {
+            println!("Failed to remove old log files: {err}");
+        }
+    }
+}
+
+fn current_path(dir: &Path) -> PathBuf {
+    dir.join("console.log")
+}
+
+fn open_current(dir: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(current_path(dir))?;
+    let size = file.metadata()?.len();
+
+    Ok((BufWriter::new(file), size))
+}
+
+/// Replaces `path` with a gzipped `path.gz`.
+fn compress_file(path: &Path) -> io::Result<()> {
+    let mut gz_path = path.as_os_str().to_owned();
+    gz_path.push(".gz");
+
+    let mut input = File::open(path)?;
+    let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+
+    fs::remove_file(path)
+}
+
+/// Deletes all but the newest `keep_files` rotated logs. Zero keeps everything.
+fn remove_old(dir: &Path, keep_files: usize) -> io::Result<()> {
+    if keep_files == 0 {
+        return Ok(());
+    }
+
+    let mut rotated = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with("console-") && (name.ends_with(".log") || name.ends_with(".log.gz")) {
+            rotated.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+
+    // Newest first, so everything past keep_files goes.
+    rotated.sort_by_key(|(modified, _)| Reverse(*modified));
+    for (_, path) in rotated.into_iter().skip(keep_files) {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use std::sync::OnceLock;
+use chrono::{NaiveDate, NaiveTime};
+use regex::Regex;
+use serde::Serialize;
+
+// The formats this understands:
+// Vanilla and Paper's log files: [12:34:56] [Server thread/INFO]: message
+// Paper's console:               [12:34:56 INFO]: message
+// Forge:                         [12:34:56] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: message
+// Newer Forge:                   [14Mar2023 12:34:56.789] [Server thread/INFO] [minecraft/DedicatedServer]: message
+// Fabric:                        [12:34:56] [Server thread/INFO] (Minecraft) message
+const LOG_LINE: &str = concat!(
+    r"^\[(?:(?<date>\d{1,2}[A-Za-z]{3}\d{4}) )?(?<time>\d{2}:\d{2}:\d{2})(?:[.,]\d+)?(?: (?<level>[A-Za-z]+))?\]",
+    r"(?: \[(?<thread>[^\]]*)/(?<thread_level>[A-Za-z]+)\])?",
+    r"(?: \[(?<logger>[^\]]*)\]:| \((?<fabric_logger>[^)]*)\))?",
+    r":? ?(?<message>.*)$"
+);
+
+// The first line of a stack trace, like java.lang.IllegalStateException: message
+const EXCEPTION: &str = r"^(?:[A-Za-z_$][\w$]*\.)+[\w$]*(?:Exception|Error|Throwable)(?::|$)";
+
+/// How severe a log line is, from least to most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal
+}
+
+impl Level {
+    /// Reads a level name, including the java.util.logging names older Bukkit servers use.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "TRACE" | "FINEST" | "FINER" => Some(Level::Trace),
+            "DEBUG" | "FINE" => Some(Level::Debug),
+            "INFO" => Some(Level::Info),
+            "WARN" | "WARNING" => Some(Level::Warn),
+            "ERROR" | "SEVERE" => Some(Level::Error),
+            "FATAL" => Some(Level::Fatal),
+            _ => None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Trace => "TRACE",
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+            Level::Fatal => "FATAL"
+        }
+    }
+}
+
+/// The parts of a line in one of the usual Minecraft log formats.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LogLine {
+    // Only newer Forge versions write the day.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date: Option<NaiveDate>,
+    pub time: NaiveTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thread: Option<String>,
+    pub level: Level,
+    // The class or mod that logged it, which only Forge and Fabric write.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logger: Option<String>,
+    pub message: String
+}
+
+impl LogLine {
+    /// Roughly how many bytes the line takes up in memory.
+    pub fn size(&self) -> usize {
+        self.thread.as_ref().map(String::len).unwrap_or(0) + self.logger.as_ref().map(String::len).unwrap_or(0) + self.message.len()
+    }
+}
+
+/// Splits a line up into its parts, or returns None if it isn't a log line with a level.
+pub fn parse(text: &str) -> Option<LogLine> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let captures = PATTERN.get_or_init(|| Regex::new(LOG_LINE).unwrap()).captures(text)?;
+
+    let level = captures.name("level").or(captures.name("thread_level")).and_then(|level| Level::parse(level.as_str()))?;
+    let time = NaiveTime::parse_from_str(captures.name("time")?.as_str(), "%H:%M:%S").ok()?;
+    let date = captures.name("date").and_then(|date| NaiveDate::parse_from_str(date.as_str(), "%d%b%Y").ok());
+
+    let logger = captures.name("logger")
+        .map(|logger| strip_marker(logger.as_str()))
+        .or(captures.name("fabric_logger").map(|logger| logger.as_str()))
+        .filter(|logger| !logger.is_empty())
+        .map(str::to_string);
+
+    Some(LogLine {
+        date,
+        time,
+        thread: captures.name("thread").map(|thread| thread.as_str().to_string()),
+        level,
+        logger,
+        message: captures.name("message").map(|message| message.as_str().to_string()).unwrap_or_default()
+    })
+}
+
+/// Drops the marker Forge puts after the logger, like [net.minecraftforge.fml.loading.FMLLoader/CORE],
+/// while keeping names that have a slash in them, like [minecraft/DedicatedServer].
+fn strip_marker(logger: &str) -> &str {
+    match logger.rsplit_once('/') {
+        Some((name, marker)) if marker.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') => name,
+        _ => logger
+    }
+}
+
+/// Whether a line that isn't a log line belongs to the one before it, like the lines of a stack trace.
+pub fn is_continuation(text: &str) -> bool {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+
+    text.starts_with([' ', '\t'])
+        || text.starts_with("Caused by: ")
+        || text.starts_with("Suppressed: ")
+        || PATTERN.get_or_init(|| Regex::new(EXCEPTION).unwrap()).is_match(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(text, "%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn parses_vanilla() {
+        let line = parse("[12:34:56] [Server thread/INFO]: Done (3.2s)! For help, type \"help\"").unwrap();
+        assert_eq!(line, LogLine {
+            date: None,
+            time: time("12:34:56"),
+            thread: Some("Server thread".to_string()),
+            level: Level::Info,
+            logger: None,
+            message: "Done (3.2s)! For help, type \"help\"".to_string()
+        });
+    }
+
+    #[test]
+    fn parses_paper_console() {
+        let line = parse("[12:34:56 WARN]: Can't keep up! Is the server overloaded?").unwrap();
+        assert_eq!(line.thread, None);
+        assert_eq!(line.level, Level::Warn);
+        assert_eq!(line.message, "Can't keep up! Is the server overloaded?");
+    }
+
+    #[test]
+    fn parses_forge() {
+        let line = parse("[12:34:56] [Server thread/ERROR] [net.minecraftforge.fml.loading.FMLLoader/CORE]: Failed to load").unwrap();
+        assert_eq!(line.level, Level::Error);
+        assert_eq!(line.logger.as_deref(), Some("net.minecraftforge.fml.loading.FMLLoader"));
+        assert_eq!(line.message, "Failed to load");
+
+        let line = parse("[12:34:56] [Server thread/INFO] [net.minecraft.server.MinecraftServer/]: Stopping server").unwrap();
+        assert_eq!(line.logger.as_deref(), Some("net.minecraft.server.MinecraftServer"));
+    }
+
+    #[test]
+    fn parses_new_forge() {
+        let line = parse("[14Mar2023 12:34:56.789] [Server thread/INFO] [minecraft/DedicatedServer]: Starting minecraft server").unwrap();
+        assert_eq!(line.date, NaiveDate::from_ymd_opt(2023, 3, 14));
+        assert_eq!(line.time, time("12:34:56"));
+        assert_eq!(line.logger.as_deref(), Some("minecraft/DedicatedServer"));
+        assert_eq!(line.message, "Starting minecraft server");
+    }
+
+    #[test]
+    fn parses_fabric() {
+        let line = parse("[12:34:56] [Server thread/INFO] (Minecraft) Preparing level \"world\"").unwrap();
+        assert_eq!(line.logger.as_deref(), Some("Minecraft"));
+        assert_eq!(line.message, "Preparing level \"world\"");
+    }
+
+    #[test]
+    fn skips_lines_without_a_level() {
+        assert_eq!(parse("Starting net.minecraft.server.Main"), None);
+        assert_eq!(parse("[12:34:56] [Server thread/NOTALEVEL]: hi"), None);
+    }
+
+    #[test]
+    fn continuations() {
+        assert!(is_continuation("\tat net.minecraft.server.MinecraftServer.run(MinecraftServer.java:100)"));
+        assert!(is_continuation("    ... 5 more"));
+        assert!(is_continuation("Caused by: java.io.IOException: Broken pipe"));
+        assert!(is_continuation("Suppressed: java.lang.RuntimeException"));
+        assert!(is_continuation("java.lang.IllegalStateException: Not ready"));
+        assert!(is_continuation("java.lang.OutOfMemoryError"));
+
+        assert!(!is_continuation("Starting net.minecraft.server.Main"));
+        assert!(!is_continuation("Exception in the middle of a sentence"));
+        assert!(!is_continuation(""));
+    }
+}
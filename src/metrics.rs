@@ -0,0 +1,204 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+/// What the server's whole process tree was using at one point in time.
+#[derive(Clone, Debug, Serialize)]
+pub struct Sample {
+    pub time: DateTime<Local>,
+    // How many processes the tree had, the server and everything it started.
+    pub processes: usize,
+    // Of one core, so a server busy on four cores shows 400.
+    pub cpu_percent: f64,
+    pub rss_bytes: u64,
+    pub threads: u64,
+    pub open_fds: u64,
+    // Bytes read from and written to disk since the last sample, per second.
+    pub read_bytes_per_second: f64,
+    pub write_bytes_per_second: f64
+}
+
+/// The counters of a single process that only ever go up, to work out rates from.
+#[derive(Clone, Copy, Default)]
+struct Counters {
+    cpu_ticks: u64,
+    read_bytes: u64,
+    write_bytes: u64
+}
+
+/// What one process is using right now.
+struct ProcessStats {
+    counters: Counters,
+    rss_pages: u64,
+    threads: u64,
+    open_fds: u64
+}
+
+/// Samples the server's process tree from /proc every `interval`, keeping the newest `max_samples`.
+pub struct Metrics {
+    samples: VecDeque<Sample>,
+    max_samples: usize,
+    interval: Duration,
+
+    // The counters of every process at the last sample, so only what happened since is counted.
+    last_counters: HashMap<u32, Counters>,
+    last_sample: Option<Instant>,
+
+    ticks_per_second: f64,
+    page_size: u64
+}
+
+impl Metrics {
+    pub fn new(interval: Duration, max_samples: usize) -> Self {
+        // SAFETY: sysconf only reads system settings.
+        let (ticks_per_second, page_size) = unsafe { (libc::sysconf(libc::_SC_CLK_TCK), libc::sysconf(libc::_SC_PAGESIZE)) };
+
+        Self {
+            samples: VecDeque::new(),
+            max_samples,
+            interval,
+
+            last_counters: HashMap::new(),
+            last_sample: None,
+
+            ticks_per_second: if ticks_per_second > 0 { ticks_per_second as f64 } else { 100.0 },
+            page_size: if page_size > 0 { page_size as u64 } else { 4096 }
+        }
+    }
+
+    /// Takes a sample of `root` and everything under it, if it's been at least `interval` since the last one.
+    pub fn sample_if_due(&mut self, root: u32) {
+        if self.last_sample.is_some_and(|last| last.elapsed() < self.interval) {
+            return;
+        }
+        let elapsed = self.last_sample.map(|last| last.elapsed().as_secs_f64());
+        self.last_sample = Some(Instant::now());
+
+        let stats: HashMap<u32, ProcessStats> = process_tree(root).into_iter()
+            .filter_map(|pid| read_process(pid).map(|stats| (pid, stats)))
+            .collect();
+        if stats.is_empty() {
+            return;
+        }
+
+        // Processes that weren't there last time started since then, so everything they did counts.
+        let mut delta = Counters::default();
+        for (pid, process) in &stats {
+            let last = self.last_counters.get(pid).copied().unwrap_or_default();
+            delta.cpu_ticks += process.counters.cpu_ticks.saturating_sub(last.cpu_ticks);
+            delta.read_bytes += process.counters.read_bytes.saturating_sub(last.read_bytes);
+            delta.write_bytes += process.counters.write_bytes.saturating_sub(last.write_bytes);
+        }
+        self.last_counters = stats.iter().map(|(pid, process)| (*pid, process.counters)).collect();
+
+        // The first sample has nothing to compare with.
+        let (cpu_percent, read_rate, write_rate) = match elapsed {
+            Some(seconds) if seconds > 0.0 => (
+                delta.cpu_ticks as f64 / self.ticks_per_second / seconds * 100.0,
+                delta.read_bytes as f64 / seconds,
+                delta.write_bytes as f64 / seconds
+            ),
+            _ => (0.0, 0.0, 0.0)
+        };
+
+        self.samples.push_back(Sample {
+            time: Local::now(),
+            processes: stats.len(),
+            cpu_percent,
+            rss_bytes: stats.values().map(|process| process.rss_pages).sum::<u64>() * self.page_size,
+            threads: stats.values().map(|process| process.threads).sum(),
+            open_fds: stats.values().map(|process| process.open_fds).sum(),
+            read_bytes_per_second: read_rate,
+            write_bytes_per_second: write_rate
+        });
+        while self.samples.len() > self.max_samples {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Forgets the last process, so the next one starts counting from zero.
+    pub fn reset(&mut self) {
+        self.last_counters.clear();
+        self.last_sample = None;
+    }
+
+    pub fn latest(&self) -> Option<&Sample> {
+        self.samples.back()
+    }
+
+    /// Every sample kept, oldest first.
+    pub fn history(&self) -> Vec<Sample> {
+        self.samples.iter().cloned().collect()
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// `root` and every process below it, found through the parent of every process in /proc.
+fn process_tree(root: u32) -> Vec<u32> {
+    let mut children: HashMap<u32, Vec<u32>> = HashMap::new();
+    if let Ok(entries) = fs::read_dir("/proc") {
+        for entry in entries.flatten() {
+            let pid = match entry.file_name().to_str().and_then(|name| name.parse::<u32>().ok()) {
+                Some(pid) => pid,
+                None => continue
+            };
+            if let Some(parent) = read_stat(pid).and_then(|fields| fields.get(1).and_then(|ppid| ppid.parse().ok())) {
+                children.entry(parent).or_default().push(pid);
+            }
+        }
+    }
+
+    let mut tree = vec![root];
+    let mut index = 0;
+    while index < tree.len() {
+        if let Some(below) = children.get(&tree[index]) {
+            tree.extend(below);
+        }
+        index += 1;
+    }
+    tree
+}
+
+/// The fields of /proc/<pid>/stat after the name, which is in parentheses and may hold spaces.
+/// So the state is field 0 here, and the parent field 1.
+fn read_stat(pid: u32) -> Option<Vec<String>> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    let (_, rest) = stat.rsplit_once(')')?;
+    Some(rest.split_whitespace().map(str::to_string).collect())
+}
+
+/// What a process is using, or None if it's gone.
+fn read_process(pid: u32) -> Option<ProcessStats> {
+    let stat = read_stat(pid)?;
+    let field = |index: usize| stat.get(index).and_then(|value| value.parse::<u64>().ok()).unwrap_or(0);
+
+    // I/O needs the same user as the server, and is simply left out without it.
+    let io = fs::read_to_string(format!("/proc/{pid}/io")).unwrap_or_default();
+    let io_field = |name: &str| io.lines()
+        .find_map(|line| line.strip_prefix(name).and_then(|value| value.trim_start_matches(':').trim().parse::<u64>().ok()))
+        .unwrap_or(0);
+
+    Some(ProcessStats {
+        counters: Counters {
+            // utime and stime.
+            cpu_ticks: field(11) + field(12),
+            read_bytes: io_field("read_bytes"),
+            write_bytes: io_field("write_bytes")
+        },
+        rss_pages: field(21),
+        threads: field(17),
+        open_fds: fs::read_dir(format!("/proc/{pid}/fd")).map(|fds| fds.count() as u64).unwrap_or(0)
+    })
+}
+
+/// How much memory the machine has, to put the server's use in perspective.
+pub fn total_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let kilobytes = meminfo.lines().find_map(|line| line.strip_prefix("MemTotal:"))?.trim().trim_end_matches("kB").trim().parse::<u64>().ok()?;
+    Some(kilobytes * 1024)
+}
@@ -0,0 +1,177 @@
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use crate::access_lists::{self, read_list, remove_from, write_list, BannedIp, BannedPlayer, OpEntry, WhitelistEntry,
+                          BANNED_IPS, BANNED_PLAYERS, OPS, WHITELIST};
+
+// What the server writes for bans that never run out.
+const FOREVER: &str = "forever";
+
+/// Something an admin can do to a player from the players page.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Kick,
+    Ban,
+    Pardon,
+    BanIp,
+    PardonIp,
+    Op,
+    Deop,
+    WhitelistAdd,
+    WhitelistRemove
+}
+
+impl Action {
+    const ALL: [Action; 9] = [
+        Action::Kick, Action::Ban, Action::Pardon, Action::BanIp, Action::PardonIp,
+        Action::Op, Action::Deop, Action::WhitelistAdd, Action::WhitelistRemove
+    ];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Kick => "kick",
+            Action::Ban => "ban",
+            Action::Pardon => "pardon",
+            Action::BanIp => "ban-ip",
+            Action::PardonIp => "pardon-ip",
+            Action::Op => "op",
+            Action::Deop => "deop",
+            Action::WhitelistAdd => "whitelist-add",
+            Action::WhitelistRemove => "whitelist-remove"
+        }
+    }
+
+    /// Whether the target is an ip address instead of a player name.
+    pub fn targets_ip(self) -> bool {
+        matches!(self, Action::BanIp | Action::PardonIp)
+    }
+
+    /// The console command that does this while the server is running.
+    pub fn command(self, target: &str, reason: &str) -> String {
+        let command = match self {
+            Action::Kick => format!("kick {target} {reason}"),
+            Action::Ban => format!("ban {target} {reason}"),
+            Action::Pardon => format!("pardon {target}"),
+            Action::BanIp => format!("ban-ip {target} {reason}"),
+            Action::PardonIp => format!("pardon-ip {target}"),
+            Action::Op => format!("op {target}"),
+            Action::Deop => format!("deop {target}"),
+            Action::WhitelistAdd => format!("whitelist add {target}"),
+            Action::WhitelistRemove => format!("whitelist remove {target}")
+        };
+        command.trim_end().to_string()
+    }
+}
+
+/// Checks a target before it goes anywhere near the console, so it can't smuggle in another command.
+pub fn check_target(action: Action, target: &str) -> Result<(), String> {
+    if action.targets_ip() {
+        return target.parse::<IpAddr>().map(|_| ()).map_err(|_| format!("{target} isn't an ip address"));
+    }
+
+    let valid = (1..=16).contains(&target.len()) && target.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid { Ok(()) } else { Err(format!("{target} isn't a valid player name")) }
+}
+
+/// A reason on a single line, since it ends up in a console command.
+pub fn clean_reason(reason: &str) -> String {
+    reason.split(['\r', '\n']).map(str::trim).filter(|part| !part.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+/// Does an action by editing the lists directly, for while the server is offline.
+/// The server reads them again when it starts.
+pub fn apply_offline(dir: &Path, action: Action, target: &str, known_uuid: Option<String>, reason: &str, user: &str) -> Result<(), String> {
+    let created = Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string();
+    let reason = if reason.is_empty() { "Banned by an operator.".to_string() } else { reason.to_string() };
+    let same_name = |name: &str| name.eq_ignore_ascii_case(target);
+    let uuid = || access_lists::resolve_uuid(dir, target, known_uuid.clone());
+
+    match action {
+        Action::Kick => Err("Nobody can be kicked while the server is offline".to_string()),
+        Action::Ban => {
+            let path = dir.join(BANNED_PLAYERS);
+            let mut list: Vec<BannedPlayer> = read_list(&path)?;
+            list.retain(|entry| !same_name(&entry.name));
+            list.push(BannedPlayer { uuid: uuid()?, name: target.to_string(), created, source: user.to_string(), expires: FOREVER.to_string(), reason });
+            write_list(&path, &list)
+        }
+        Action::Pardon => remove_from::<BannedPlayer>(&dir.join(BANNED_PLAYERS), |entry| same_name(&entry.name)),
+        Action::BanIp => {
+            let path = dir.join(BANNED_IPS);
+            let mut list: Vec<BannedIp> = read_list(&path)?;
+            list.retain(|entry| entry.ip != target);
+            list.push(BannedIp { ip: target.to_string(), created, source: user.to_string(), expires: FOREVER.to_string(), reason });
+            write_list(&path, &list)
+        }
+        Action::PardonIp => remove_from::<BannedIp>(&dir.join(BANNED_IPS), |entry| entry.ip == target),
+        Action::Op => {
+            let path = dir.join(OPS);
+            let mut list: Vec<OpEntry> = read_list(&path)?;
+            if !list.iter().any(|entry| same_name(&entry.name)) {
+                list.push(OpEntry { uuid: uuid()?, name: target.to_string(), level: 4, bypasses_player_limit: false });
+            }
+            write_list(&path, &list)
+        }
+        Action::Deop => remove_from::<OpEntry>(&dir.join(OPS), |entry| same_name(&entry.name)),
+        Action::WhitelistAdd => {
+            let path = dir.join(WHITELIST);
+            let mut list: Vec<WhitelistEntry> = read_list(&path)?;
+            if !list.iter().any(|entry| same_name(&entry.name)) {
+                list.push(WhitelistEntry { uuid: uuid()?, name: target.to_string() });
+            }
+            write_list(&path, &list)
+        }
+        Action::WhitelistRemove => remove_from::<WhitelistEntry>(&dir.join(WHITELIST), |entry| same_name(&entry.name))
+    }
+}
+
+/// Who did what to whom, and why.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: DateTime<Local>,
+    pub user: String,
+    pub action: Action,
+    pub target: String,
+    pub reason: String,
+    // Whether it went through the console or straight into the files.
+    pub applied_by: String
+}
+
+/// An append only file of every moderation action, one json object per line.
+pub struct AuditLog {
+    path: PathBuf
+}
+
+impl AuditLog {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn record(&self, entry: &AuditEntry) -> Result<(), String> {
+        let line = serde_json::to_string(entry).map_err(|err| err.to_string())?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)
+            .map_err(|err| format!("Failed to open {}: {err}", self.path.display()))?;
+        writeln!(file, "{line}").map_err(|err| format!("Failed to write to {}: {err}", self.path.display()))
+    }
+
+    /// The newest `count` entries, newest first.
+    pub fn recent(&self, count: usize) -> Vec<AuditEntry> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(_) => return vec![]
+        };
+
+        let entries: Vec<AuditEntry> = BufReader::new(file).lines().map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect();
+        entries.into_iter().rev().take(count).collect()
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use crate::access_lists::write_atomic;
+use crate::game_events::GameEvent;
+use crate::log_file::log;
+
+/// Someone who is on the server right now.
+#[derive(Clone, Debug, Serialize)]
+pub struct OnlinePlayer {
+    pub name: String,
+    pub uuid: Option<String>,
+    pub ip: Option<String>,
+    pub joined: DateTime<Local>
+}
+
+/// Everything remembered about a player across all of their sessions.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlayerHistory {
+    pub name: String,
+    #[serde(default)]
+    pub uuid: Option<String>,
+    pub first_seen: DateTime<Local>,
+    pub last_seen: DateTime<Local>,
+    // Only counts finished sessions.
+    pub playtime_seconds: u64,
+    pub sessions: u64
+}
+
+/// Who is online, and the play history of everyone who ever joined.
+/// The history is saved to a json file whenever someone joins or leaves, so it survives restarts.
+pub struct Players {
+    online: BTreeMap<String, OnlinePlayer>,
+    history: HashMap<String, PlayerHistory>,
+    path: PathBuf
+}
+
+impl Players {
+    /// Loads the history from `path`, starting empty if there isn't one yet.
+    /// A file that can't be read is moved aside first, so the next save doesn't write over it.
+    pub fn load(path: PathBuf) -> Self {
+        let history = match fs::read_to_string(&path) {
+            Ok(contents) => match serde_json::from_str::<Vec<PlayerHistory>>(&contents) {
+                Ok(history) => history.into_iter().map(|player| (player.name.clone(), player)).collect(),
+                Err(err) => {
+                    log!("Failed to read the player history in {}: {err}", path.display());
+                    move_aside(&path);
+                    HashMap::new()
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => {
+                log!("Failed to read the player history in {}: {err}", path.display());
+                move_aside(&path);
+                HashMap::new()
+            }
+        };
+
+        Self {
+            online: BTreeMap::new(),
+            history,
+            path
+        }
+    }
+
+    /// Keeps track of joins and leaves, and of the `list` output.
+    pub fn handle_event(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::PlayerJoined { name, uuid, ip } => self.join(name, uuid.clone(), ip.clone()),
+            GameEvent::PlayerLeft { name, .. } => self.leave(name),
+            GameEvent::PlayerList { names } => {
+                if !self.sync(names) {
+                    return;
+                }
+            }
+            _ => return
+        }
+
+        self.save();
+    }
+
+    /// Ends every session, for when the server goes down.
+    pub fn leave_all(&mut self) {
+        if self.online.is_empty() {
+            return;
+        }
+
+        let names: Vec<String> = self.online.keys().cloned().collect();
+        for name in names {
+            self.leave(&name);
+        }
+        self.save();
+    }
+
+    pub fn online(&self) -> Vec<OnlinePlayer> {
+        self.online.values().cloned().collect()
+    }
+
+    /// The UUID a player last joined with, if they ever did.
+    pub fn uuid_of(&self, name: &str) -> Option<String> {
+        self.history.values().find(|player| player.name.eq_ignore_ascii_case(name)).and_then(|player| player.uuid.clone())
+    }
+
+    /// Everyone who ever played, most recently seen first.
+    pub fn history(&self) -> Vec<PlayerHistory> {
+        let mut history: Vec<PlayerHistory> = self.history.values().cloned().collect();
+        history.sort_by_key(|player| std::cmp::Reverse(player.last_seen));
+        history
+    }
+
+    /// What the players page shows, with times and playtimes written out for people.
+    pub fn view(&self) -> serde_json::Value {
+        let now = Local::now();
+        let time = |time: DateTime<Local>| time.format("%Y-%m-%d %H:%M").to_string();
+
+        let online: Vec<serde_json::Value> = self.online.values().map(|player| json!({
+            "name": player.name,
+            "uuid": player.uuid,
+            "ip": player.ip,
+            "joined": time(player.joined),
+            "session": format_duration((now - player.joined).num_seconds().max(0) as u64)
+        })).collect();
+
+        let history: Vec<serde_json::Value> = self.history().into_iter().map(|player| json!({
+            "name": player.name,
+            "uuid": player.uuid,
+            "first_seen": time(player.first_seen),
+            "last_seen": time(player.last_seen),
+            "playtime": format_duration(player.playtime_seconds),
+            "sessions": player.sessions
+        })).collect();
+
+        json!({"online_count": online.len(), "online": online, "history": history})
+    }
+
+    fn join(&mut self, name: &str, uuid: Option<String>, ip: Option<String>) {
+        let now = Local::now();
+
+        let history = self.history.entry(name.to_string()).or_insert_with(|| PlayerHistory {
+            name: name.to_string(),
+            uuid: None,
+            first_seen: now,
+            last_seen: now,
+            playtime_seconds: 0,
+            sessions: 0
+        });
+        history.last_seen = now;
+        history.sessions += 1;
+        if uuid.is_some() {
+            history.uuid = uuid.clone();
+        }
+
+        self.online.insert(name.to_string(), OnlinePlayer { name: name.to_string(), uuid, ip, joined: now });
+    }
+
+    fn leave(&mut self, name: &str) {
+        let player = match self.online.remove(name) {
+            Some(player) => player,
+            None => return
+        };
+
+        let now = Local::now();
+        if let Some(history) = self.history.get_mut(name) {
+            history.last_seen = now;
+            history.playtime_seconds += (now - player.joined).num_seconds().max(0) as u64;
+        }
+    }
+
+    /// Brings the online list in line with what `list` said, in case a join or leave was missed.
+    /// Returns whether anything changed.
+    fn sync(&mut self, names: &[String]) -> bool {
+        let gone: Vec<String> = self.online.keys().filter(|name| !names.contains(name)).cloned().collect();
+        for name in &gone {
+            self.leave(name);
+        }
+
+        let mut changed = !gone.is_empty();
+        for name in names {
+            if !self.online.contains_key(name) {
+                self.join(name, None, None);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    fn save(&self) {
+        let contents = match serde_json::to_string_pretty(&self.history()) {
+            Ok(contents) => contents,
+            Err(err) => {
+                log!("Failed to save the player history: {err}");
+                return;
+            }
+        };
+
+        if let Err(err) = write_atomic(&self.path, contents.as_bytes()) {
+            log!("Failed to save the player history to {}: {err}", self.path.display());
+        }
+    }
+}
+
+/// Renames a history that couldn't be read to <path>.broken-<time>, where it's kept for someone to look at.
+fn move_aside(path: &Path) {
+    let mut broken = path.as_os_str().to_owned();
+    broken.push(format!(".broken-{}", Local::now().format("%Y%m%d-%H%M%S")));
+
+    match fs::rename(path, &broken) {
+        Ok(()) => log!("Moved it to {} and started a new one", Path::new(&broken).display()),
+        Err(err) => log!("Failed to move it aside: {err}")
+    }
+}
+
+/// Playtime the way people read it, like 3h 25m.
+fn format_duration(seconds: u64) -> String {
+    let (hours, minutes) = (seconds / 3600, seconds / 60 % 60);
+    match (hours, minutes) {
+        (0, 0) => format!("{seconds}s"),
+        (0, _) => format!("{minutes}m"),
+        _ => format!("{hours}h {minutes}m")
+    }
+}
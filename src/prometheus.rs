@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+// Upper bounds of the request duration buckets, in seconds.
+const DURATION_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// What every web worker has seen, recorded as responses go out from whichever thread writes them.
+static HTTP: Mutex<HttpStats> = Mutex::new(HttpStats::new());
+
+/// How long requests to one route took.
+#[derive(Clone, Default)]
+struct Histogram {
+    // How many requests fell in each bucket, not counting the ones before it.
+    buckets: [u64; DURATION_BUCKETS.len()],
+    sum: f64,
+    count: u64
+}
+
+struct HttpStats {
+    // By route and status code.
+    requests: BTreeMap<(String, u16), u64>,
+    durations: BTreeMap<String, Histogram>,
+    failed_logins: u64
+}
+
+impl HttpStats {
+    const fn new() -> Self {
+        Self {
+            requests: BTreeMap::new(),
+            durations: BTreeMap::new(),
+            failed_logins: 0
+        }
+    }
+}
+
+/// Counts a response, and how long it took from the request coming in.
+/// Anything that wasn't found is counted as one route, so scanners can't make up endless labels.
+pub fn record_request(route: &str, code: u16, seconds: f64) {
+    let route = if code == 404 { "unknown".to_string() } else { route.to_string() };
+    let mut stats = HTTP.lock().unwrap();
+
+    *stats.requests.entry((route.clone(), code)).or_default() += 1;
+
+    let histogram = stats.durations.entry(route).or_default();
+    if let Some(bucket) = DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+        histogram.buckets[bucket] += 1;
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+pub fn record_failed_login() {
+    HTTP.lock().unwrap().failed_logins += 1;
+}
+
+/// Writes metrics in the Prometheus text format.
+#[derive(Default)]
+pub struct Exposition {
+    text: String
+}
+
+impl Exposition {
+    /// Describes a metric, which has to come before any of its samples.
+    pub fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter().map(|(key, value)| format!("{key}=\"{}\"", escape(value))).collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {value}");
+    }
+
+    /// Adds the request counts, durations and failed logins.
+    pub fn http(&mut self) {
+        let stats = HTTP.lock().unwrap();
+
+        self.header("mcserver_http_requests_total", "counter", "Requests answered, by route and status code.");
+        for ((route, code), count) in &stats.requests {
+            self.sample("mcserver_http_requests_total", &[("route", route), ("code", &code.to_string())], *count as f64);
+        }
+
+        self.header("mcserver_http_request_duration_seconds", "histogram", "How long requests took to answer.");
+        for (route, histogram) in &stats.durations {
+            let mut cumulative = 0;
+            for (bound, count) in DURATION_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                self.sample("mcserver_http_request_duration_seconds_bucket", &[("route", route), ("le", &bound.to_string())], cumulative as f64);
+            }
+            self.sample("mcserver_http_request_duration_seconds_bucket", &[("route", route), ("le", "+Inf")], histogram.count as f64);
+            self.sample("mcserver_http_request_duration_seconds_sum", &[("route", route)], histogram.sum);
+            self.sample("mcserver_http_request_duration_seconds_count", &[("route", route)], histogram.count as f64);
+        }
+
+        self.header("mcserver_failed_logins_total", "counter", "Logins with a wrong username or password.");
+        self.sample("mcserver_failed_logins_total", &[], stats.failed_logins as f64);
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+/// A pseudo-terminal pair. The child gets the slave end as its terminal,
+/// and we read its output from and write its input to the master end.
+pub struct Pty {
+    pub master: File,
+    slave: OwnedFd
+}
+
+impl Pty {
+    /// Opens a new PTY with the given window size, with echo turned off
+    /// so commands we send don't show up twice in the console.
+    pub fn open(columns: u16, rows: u16) -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let window = libc::winsize {
+            ws_row: rows,
+            ws_col: columns,
+            ws_xpixel: 0,
+            ws_ypixel: 0
+        };
+
+        // SAFETY: both fds are out-parameters, and the name buffer and termios are allowed to be null.
+        let result = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &window) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: openpty succeeded, so both fds are open and owned by nobody else.
+        let (master, slave) = unsafe { (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+        // SAFETY: termios is plain old data, and tcgetattr fills it in before we read it.
+        unsafe {
+            let mut termios = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(slave.as_raw_fd(), &mut termios) == 0 {
+                termios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+                libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios);
+            }
+        }
+
+        Ok(Self { master, slave })
+    }
+
+    /// Spawns `command` with the slave end as its stdin, stdout, stderr and controlling terminal.
+    pub fn spawn(self, command: &mut Command) -> io::Result<(Child, File)> {
+        command
+            .stdin(self.slave.try_clone()?)
+            .stdout(self.slave.try_clone()?)
+            .stderr(self.slave.try_clone()?);
+
+        // Plenty of programs only use colors and line editing if they know what terminal they're on.
+        if std::env::var_os("TERM").is_none() {
+            command.env("TERM", "xterm-256color");
+        }
+
+        // SAFETY: only async-signal-safe calls happen between fork and exec.
+        unsafe {
+            command.pre_exec(|| {
+                // Start a new session, so the PTY can become our controlling terminal.
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = command.spawn()?;
+
+        // Our copy of the slave is dropped here, so reading the master ends once the child is gone.
+        Ok((child, self.master))
+    }
+}
+
+/// Turns a raw line from a terminal into what it would look like on screen.
+/// Carriage returns overwrite the line, and every escape sequence except colors (SGR) is dropped.
+pub fn clean_line(text: &str) -> String {
+    // A carriage return starts the line over, like when JLine redraws its prompt.
+    let text = text.rsplit('\r').find(|part| !part.is_empty()).unwrap_or("");
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters, then a final byte from @ to ~.
+                Some('[') => {
+                    let mut sequence = String::from("\x1b[");
+                    for c in chars.by_ref() {
+                        sequence.push(c);
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+
+                    // Only colors and styles are kept, the console knows how to show those.
+                    if sequence.ends_with('m') {
+                        result.push_str(&sequence);
+                    }
+                }
+                // OSC: ends with BEL or ESC \.
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Anything else is a two character sequence.
+                _ => {}
+            },
+            // Backspace removes what came before it.
+            '\x08' => { result.pop(); }
+            c if c.is_control() && c != '\t' => {}
+            c => result.push(c)
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carriage_returns_start_the_line_over() {
+        assert_eq!(clean_line("> \r[12:00:00 INFO]: Done"), "[12:00:00 INFO]: Done");
+        assert_eq!(clean_line("Loading 10%\rLoading 50%\rLoading 100%"), "Loading 100%");
+        // A trailing \r from a \r\n ending doesn't wipe the line.
+        assert_eq!(clean_line("text\r"), "text");
+        assert_eq!(clean_line("\r"), "");
+    }
+
+    #[test]
+    fn cursor_and_erase_escapes_are_dropped() {
+        assert_eq!(clean_line("\x1b[2K\x1b[1G> \x1b[3Dtext\x1b[K"), "> text");
+        assert_eq!(clean_line("\x1b[?25lhidden cursor\x1b[?25h"), "hidden cursor");
+        assert_eq!(clean_line("\x1b]0;Minecraft server\x07title"), "title");
+        assert_eq!(clean_line("\x1b]0;title\x1b\\after"), "after");
+        assert_eq!(clean_line("\x1b7saved\x1b8"), "saved");
+    }
+
+    #[test]
+    fn colors_are_kept() {
+        assert_eq!(clean_line("\x1b[33;1mwarning\x1b[m"), "\x1b[33;1mwarning\x1b[m");
+    }
+
+    #[test]
+    fn backspaces_and_control_characters() {
+        assert_eq!(clean_line("helo\x08lo"), "hello");
+        assert_eq!(clean_line("a\tb\x07c"), "a\tbc");
+    }
+}
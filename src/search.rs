@@ -0,0 +1,396 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use flate2::read::GzDecoder;
+use regex::Regex;
+use serde::Serialize;
+use crate::io_handler::ConsoleLine;
+use crate::log_file::log;
+use crate::log_parser::{self, Level};
+use crate::server::{url_encode, ServerStream};
+
+/// Where to look for matches.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SearchSource {
+    // The server's logs/ folder, including the rotated .log.gz files.
+    Files,
+    // The lines currently held in memory by the console.
+    Live,
+    Both
+}
+
+/// Everything a search can filter on, read from the query string.
+pub struct SearchQuery {
+    pub text: Option<String>,
+    pub regex: Option<Regex>,
+    // Only lines at this level or worse.
+    pub level: Option<Level>,
+    pub from: Option<NaiveDateTime>,
+    pub to: Option<NaiveDateTime>,
+    pub source: SearchSource,
+    // One based.
+    pub page: usize,
+    pub page_size: usize,
+    pub context: usize
+}
+
+impl SearchQuery {
+    pub fn from_request(request: &ServerStream) -> Result<Self, String> {
+        let get = |key: &str| request.get_query(key).filter(|value| !value.is_empty());
+
+        let regex = match get("regex") {
+            Some(pattern) => Some(Regex::new(&pattern).map_err(|err| format!("Invalid regex: {err}"))?),
+            None => None
+        };
+
+        let level = match get("level") {
+            Some(level) => Some(Level::parse(&level).ok_or(format!("Unknown level {level}"))?),
+            None => None
+        };
+
+        let source = match get("source").as_deref() {
+            Some("files") => SearchSource::Files,
+            Some("live") => SearchSource::Live,
+            Some("both") | None => SearchSource::Both,
+            Some(source) => { return Err(format!("Unknown source {source}")); }
+        };
+
+        let number = |key: &str, default: usize, max: usize| -> Result<usize, String> {
+            match get(key) {
+                Some(value) => value.parse::<usize>().map(|value| value.min(max)).map_err(|_| format!("{key} has to be a number")),
+                None => Ok(default)
+            }
+        };
+
+        Ok(Self {
+            text: get("text"),
+            regex,
+            level,
+            from: get("from").map(|from| parse_time_input(&from)).transpose()?,
+            to: get("to").map(|to| parse_time_input(&to)).transpose()?,
+            source,
+            // Far past any real log, but small enough that working out where the page starts can't overflow.
+            page: number("page", 1, 1_000_000)?.max(1),
+            page_size: number("page_size", 50, 500)?.max(1),
+            context: number("context", 2, 20)?
+        })
+    }
+
+    /// Whether there's anything to filter on at all.
+    pub fn is_empty(&self) -> bool {
+        self.text.is_none() && self.regex.is_none() && self.level.is_none() && self.from.is_none() && self.to.is_none()
+    }
+
+    /// Whether a single line passes every filter.
+    pub fn matches(&self, line: &SearchLine) -> bool {
+        let SearchLine { time, level: line_level, text } = line;
+        if let Some(search) = &self.text {
+            if !text.to_lowercase().contains(&search.to_lowercase()) {
+                return false;
+            }
+        }
+
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(text) {
+                return false;
+            }
+        }
+
+        if let Some(level) = self.level {
+            match line_level {
+                Some(line_level) if *line_level >= level => {}
+                _ => return false
+            }
+        }
+
+        // Lines without a time can't be placed in a range.
+        if self.from.is_some() || self.to.is_some() {
+            let time = match *time {
+                Some(time) => time,
+                None => return false
+            };
+            if self.from.map(|from| time < from).unwrap_or(false) || self.to.map(|to| time > to).unwrap_or(false) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// The query string for another page of these same results.
+    pub fn page_link(&self, request: &ServerStream, page: usize) -> String {
+        let mut params = vec![];
+        for key in ["server", "text", "regex", "level", "from", "to", "source", "page_size", "context"] {
+            if let Some(value) = request.get_query(key).filter(|value| !value.is_empty()) {
+                params.push(format!("{key}={}", url_encode(&value)));
+            }
+        }
+        params.push(format!("page={page}"));
+
+        format!("?{}", params.join("&"))
+    }
+}
+
+/// A line to be searched, with what could be read from it.
+pub struct SearchLine {
+    pub time: Option<NaiveDateTime>,
+    // Stack traces get the level of the entry they belong to.
+    pub level: Option<Level>,
+    pub text: String
+}
+
+/// A line that matched, with the lines around it.
+#[derive(Serialize)]
+pub struct SearchMatch {
+    // The file it was found in, or "live" for the console.
+    pub source: String,
+    pub line_number: usize,
+    pub time: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<Level>,
+    pub text: String,
+    pub before: Vec<String>,
+    pub after: Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct SearchResults {
+    pub matches: Vec<SearchMatch>,
+    pub total: usize,
+    pub page: usize,
+    pub pages: usize
+}
+
+/// Keeps count of every match, and the full details of the ones on the requested page.
+struct Collector<'a> {
+    query: &'a SearchQuery,
+    skip: usize,
+    total: usize,
+    matches: Vec<SearchMatch>
+}
+
+impl<'a> Collector<'a> {
+    /// Runs the query over one source's lines, in order.
+    fn scan(&mut self, source: &str, lines: impl Iterator<Item = SearchLine>) {
+        let context = self.query.context;
+        let mut before: VecDeque<String> = VecDeque::with_capacity(context + 1);
+        // Matches on this page that still want lines after them.
+        let mut waiting: Vec<usize> = vec![];
+
+        for (index, line) in lines.enumerate() {
+            waiting.retain(|&match_index| {
+                let found = &mut self.matches[match_index];
+                found.after.push(line.text.clone());
+                found.after.len() < context
+            });
+
+            if self.query.matches(&line) {
+                self.total += 1;
+
+                let on_page = self.total > self.skip && self.matches.len() < self.query.page_size;
+                if on_page {
+                    self.matches.push(SearchMatch {
+                        source: source.to_string(),
+                        line_number: index + 1,
+                        time: line.time.map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string()),
+                        level: line.level,
+                        text: line.text.clone(),
+                        before: before.iter().cloned().collect(),
+                        after: vec![]
+                    });
+                    if context > 0 {
+                        waiting.push(self.matches.len() - 1);
+                    }
+                }
+            }
+
+            if context > 0 {
+                before.push_back(line.text);
+                if before.len() > context {
+                    before.pop_front();
+                }
+            }
+        }
+    }
+}
+
+/// Searches the server's log files and/or the live console, oldest first.
+pub fn search(query: &SearchQuery, live: &[Arc<ConsoleLine>], logs_dir: &Path) -> SearchResults {
+    let mut collector = Collector {
+        query,
+        skip: (query.page - 1).saturating_mul(query.page_size),
+        total: 0,
+        matches: vec![]
+    };
+
+    if query.source != SearchSource::Live {
+        for (path, date) in log_files(logs_dir) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            match open_log(&path) {
+                Ok(reader) => collector.scan(&name, dated_lines(reader, date)),
+                Err(err) => log!("Failed to search {}: {err}", path.display())
+            }
+        }
+    }
+
+    if query.source != SearchSource::Files {
+        collector.scan("live", live.iter().map(|line| SearchLine {
+            time: Some(line.time.naive_local()),
+            level: line.level,
+            text: line.text.clone()
+        }));
+    }
+
+    let pages = collector.total.div_ceil(query.page_size).max(1);
+
+    SearchResults {
+        matches: collector.matches,
+        total: collector.total,
+        page: query.page,
+        pages
+    }
+}
+
+/// Every log in the folder with the day it starts on, oldest first with latest.log last.
+fn log_files(logs_dir: &Path) -> Vec<(PathBuf, NaiveDate)> {
+    let mut files = vec![];
+    let mut latest = None;
+
+    let entries = match fs::read_dir(logs_dir) {
+        Ok(entries) => entries,
+        Err(_) => return files
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        if name == "latest.log" {
+            // latest.log is still being written, so the day it was last touched is the best guess.
+            let date = entry.metadata().and_then(|meta| meta.modified())
+                .map(|modified| DateTime::<Local>::from(modified).date_naive())
+                .unwrap_or_else(|_| Local::now().date_naive());
+            latest = Some((path, date));
+        } else if name.ends_with(".log") || name.ends_with(".log.gz") {
+            // Rotated logs are named like 2023-05-14-1.log.gz
+            if let Some(date) = name.get(..10).and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()) {
+                files.push((path, date));
+            }
+        }
+    }
+
+    // The names sort by date and then by number, but 10 comes before 2 as text.
+    files.sort_by_key(|(path, date)| {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let number = name[10..].trim_start_matches('-').split('.').next().and_then(|number| number.parse::<u32>().ok()).unwrap_or(0);
+        (*date, number)
+    });
+    files.extend(latest);
+
+    files
+}
+
+fn open_log(path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+
+    if path.extension().map(|extension| extension == "gz").unwrap_or(false) {
+        Ok(Box::new(BufReader::new(GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+/// Reads every line with its time and level, starting on the day the file started.
+/// A time going backwards on a line without a date means the log went past midnight.
+fn dated_lines(reader: Box<dyn BufRead>, mut date: NaiveDate) -> impl Iterator<Item = SearchLine> {
+    let mut last_time: Option<NaiveTime> = None;
+    let mut last_level: Option<Level> = None;
+
+    reader.lines().map_while(Result::ok).map(move |text| {
+        let (time, level) = match log_parser::parse(&text) {
+            Some(log) => {
+                match log.date {
+                    Some(line_date) => date = line_date,
+                    None if last_time.map(|last| log.time < last).unwrap_or(false) => date = date.succ_opt().unwrap_or(date),
+                    None => {}
+                }
+                last_time = Some(log.time);
+                (Some(date.and_time(log.time)), Some(log.level))
+            }
+            None if log_parser::is_continuation(&text) => (last_time.map(|time| date.and_time(time)), last_level),
+            None => (None, None)
+        };
+        last_level = level;
+
+        SearchLine { time, level, text }
+    })
+}
+
+/// Reads a time from an html datetime-local input, or just a date.
+fn parse_time_input(value: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .or_else(|_| NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S"))
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y-%m-%d").map(|date| date.and_time(NaiveTime::MIN)))
+        .map_err(|_| format!("Invalid time {value}, use YYYY-MM-DDTHH:MM"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::io::Cursor;
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    #[test]
+    fn log_files_sort_by_date_then_number() {
+        let dir = env::temp_dir().join(format!("mcserver-handler-search-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["latest.log", "2023-05-14-10.log.gz", "2023-05-14-2.log.gz", "2023-05-13-1.log.gz", "2023-05-14-1.log", "notes.txt", "debug.log"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let names: Vec<String> = log_files(&dir).iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["2023-05-13-1.log.gz", "2023-05-14-1.log", "2023-05-14-2.log.gz", "2023-05-14-10.log.gz", "latest.log"]);
+        assert_eq!(log_files(&dir)[0].1, date("2023-05-13"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dated_lines_roll_over_at_midnight() {
+        let log = concat!(
+            "[23:59:58] [Server thread/INFO]: Before midnight\n",
+            "[00:00:01] [Server thread/WARN]: After midnight\n",
+            "java.lang.IllegalStateException: Broken\n",
+            "\tat Example.run(Example.java:1)\n",
+            "Not a log line\n",
+            "[00:00:02] [Server thread/INFO]: Same day\n"
+        );
+        let lines: Vec<SearchLine> = dated_lines(Box::new(Cursor::new(log.as_bytes().to_vec())), date("2023-05-14")).collect();
+
+        let time = |text: &str| Some(NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap());
+        assert_eq!(lines[0].time, time("2023-05-14 23:59:58"));
+        assert_eq!(lines[1].time, time("2023-05-15 00:00:01"));
+        // The stack trace goes with the entry before it.
+        assert_eq!(lines[2].time, time("2023-05-15 00:00:01"));
+        assert_eq!(lines[3].level, Some(Level::Warn));
+        assert_eq!(lines[4].time, None);
+        assert_eq!(lines[5].time, time("2023-05-15 00:00:02"));
+    }
+
+    #[test]
+    fn dated_lines_take_the_date_from_the_line() {
+        let log = "[14Mar2023 12:34:56.789] [Server thread/INFO] [minecraft/DedicatedServer]: Starting\n";
+        let lines: Vec<SearchLine> = dated_lines(Box::new(Cursor::new(log.as_bytes().to_vec())), date("2023-01-01")).collect();
+        assert_eq!(lines[0].time, Some(date("2023-03-14").and_hms_opt(12, 34, 56).unwrap()));
+    }
+}
@@ -1,96 +1,427 @@
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Read, Write};
 use serde_json::{json, Map, Value};
 use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, mpsc};
-use std::sync::mpsc::Receiver;
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc::Sender;
 use std::thread;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslStream};
+use std::time::{Duration, Instant, SystemTime};
+use openssl::nid::Nid;
+use openssl::ssl::{NameType, SniError, SslAcceptor, SslContextBuilder, SslFiletype, SslMethod, SslStream, SslVerifyMode};
+use openssl::x509::X509Name;
+use crate::config::{CertificateConfig, ClientCertMode, Config, TlsMode};
+use crate::event::Event;
+use crate::log_file::log;
+use crate::prometheus;
+
+// Anything bigger is refused before it's read, so a client can't make us buffer whatever it likes.
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// The acceptor every https worker uses, swapped out whole when the certificate is reloaded.
+type SharedAcceptor = Arc<RwLock<Arc<SslAcceptor>>>;
+
+/// Everything needed to build an acceptor, kept so it can be built again on reload.
+#[derive(Clone)]
+struct TlsSettings {
+    cert_path: String,
+    key_path: String,
+    // Extra certificates picked by the hostname the client asks for.
+    sni_certificates: Vec<CertificateConfig>,
+    client_cert_mode: ClientCertMode,
+    client_ca_path: Option<String>
+}
 
 pub struct Server {
-    pub tcp_receiver: Receiver<TcpStream>,
-    pub acceptor: Arc<SslAcceptor>
+    acceptor: Option<SharedAcceptor>,
+    tls: TlsSettings,
+
+    // When the certificate files were last changed, to notice renewals.
+    cert_modified: Option<Vec<SystemTime>>
 }
 
 impl Server {
-    pub fn new(threads: u32, addr: &str) -> Self {
-        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-        acceptor.set_private_key_file("key.pem", SslFiletype::PEM).unwrap();
-        acceptor.set_certificate_chain_file("cert.pem").unwrap();
-        acceptor.check_private_key().unwrap();
-        let acceptor = Arc::new(acceptor.build());
-
-        // Init the MPSC sender and receiver.
-        let (sender, receiver) = mpsc::channel();
-
-        // Create the server listener
-        let listener = TcpListener::bind(addr).unwrap();
-
-        // Init the handler threads.
-        for _ in 0..threads {
-            let tcp_listener = listener.try_clone().unwrap();
-            let tcp_sender = sender.clone();
-            // Init the server here.
-            thread::spawn(move || {
-                for stream in tcp_listener.incoming() {
-                    let stream = stream.unwrap();
-
-                    // Attempt to send the tcp_stream to the main thread
-                    match tcp_sender.send(stream) {
-                        Ok(_) => {}
-                        // Ignore the error here because the server is most
-                        // likely closed.
-                        Err(_) => { break; }
-                    }
+    pub fn new(config: &Config, sender: Sender<Event>) -> Self {
+        let timeout = Duration::from_secs(config.request_timeout);
+
+        let tls = TlsSettings {
+            cert_path: config.cert_path.clone(),
+            key_path: config.key_path.clone(),
+            sni_certificates: config.certificates.clone(),
+            client_cert_mode: config.client_cert_mode,
+            client_ca_path: config.client_ca_path.clone()
+        };
+
+        let acceptor = match config.tls_mode {
+            TlsMode::Plain => None,
+            TlsMode::Tls | TlsMode::Both => {
+                let acceptor = build_acceptor(&tls)
+                    .unwrap_or_else(|err| panic!("{err}"));
+                Some(Arc::new(RwLock::new(Arc::new(acceptor))))
+            }
+        };
+
+        // The main listener is https unless we're told to run plain only.
+        let request_sender = sender.clone();
+        let shared_acceptor = acceptor.clone();
+        spawn_listener(&config.host_ip, config.receive_threads, timeout, Arc::new(move |stream| {
+            // Grab whichever acceptor is current, so a reload takes effect on the next connection.
+            let acceptor = shared_acceptor.as_ref().map(|shared| shared.read().unwrap().clone());
+
+            let request = match parse_stream(stream, acceptor.as_deref(), timeout) {
+                Ok(req) => req,
+                Err(_) => { return true }
+            };
+
+            // Attempt to send the parsed request to the main thread
+            // If that fails the server is most likely closed.
+            request_sender.send(Event::Request(request)).is_ok()
+        }));
+
+        // In both mode the panel is served over plain http as well.
+        if config.tls_mode == TlsMode::Both {
+            match &config.plain_host_ip {
+                Some(plain_ip) => {
+                    let request_sender = sender.clone();
+                    spawn_listener(plain_ip, config.receive_threads, timeout, Arc::new(move |stream| {
+                        let request = match parse_stream(stream, None, timeout) {
+                            Ok(req) => req,
+                            Err(_) => { return true }
+                        };
+
+                        request_sender.send(Event::Request(request)).is_ok()
+                    }));
                 }
-            });
+                None => panic!("tls_mode \"both\" needs a plain_host_ip to listen on!")
+            }
+        }
+
+        // Anything that hits the redirect listener gets bounced over to https.
+        if let Some(redirect_ip) = &config.redirect_host_ip {
+            let https_port = config.host_ip.rsplit_once(':').map(|(_, port)| port.to_string()).unwrap_or_default();
+            spawn_listener(redirect_ip, config.receive_threads, timeout, Arc::new(move |stream| {
+                if let Ok(request) = parse_stream(stream, None, timeout) {
+                    let request = request.counted_as("redirect");
+                    let location = request.https_location(&https_port);
+                    request.write_request("HTTP/1.1 301 Moved Permanently", "", vec![&format!("Location: {location}")]);
+                }
+                true
+            }));
         }
 
         // Return the server after everything is done running.
         Self {
-            tcp_receiver: receiver,
-            acceptor
+            acceptor,
+            cert_modified: modified_times(&tls),
+            tls
+        }
+
+    }
+
+    /// Loads the certificate and key again, and swaps them in only if they're valid.
+    /// On failure the old certificate stays in use.
+    pub fn reload_tls(&mut self) -> Result<(), String> {
+        let shared = match &self.acceptor {
+            Some(shared) => shared,
+            None => { return Err("TLS is turned off, there is nothing to reload".to_string()); }
+        };
+
+        self.cert_modified = modified_times(&self.tls);
+
+        let acceptor = build_acceptor(&self.tls)?;
+        *shared.write().unwrap() = Arc::new(acceptor);
+
+        Ok(())
+    }
+
+    /// Reloads the certificate if its files changed since the last load.
+    /// Returns None when nothing changed.
+    pub fn reload_if_changed(&mut self) -> Option<Result<(), String>> {
+        self.acceptor.as_ref()?;
+
+        let modified = modified_times(&self.tls);
+        if modified.is_none() || modified == self.cert_modified {
+            return None;
+        }
+
+        Some(self.reload_tls())
+    }
+}
+
+/// Loads the certificate and key into an acceptor, making sure they belong together.
+/// Clients asking for one of the SNI hostnames get that hostname's certificate instead.
+fn build_acceptor(tls: &TlsSettings) -> Result<SslAcceptor, String> {
+    let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(|err| err.to_string())?;
+    configure_context(&mut acceptor, &tls.cert_path, &tls.key_path, tls)?;
+
+    // Build every SNI context up front, so a broken one fails the whole load.
+    let mut contexts = HashMap::new();
+    for certificate in &tls.sni_certificates {
+        let mut context = SslAcceptor::mozilla_intermediate(SslMethod::tls()).map_err(|err| err.to_string())?;
+        configure_context(&mut context, &certificate.cert_path, &certificate.key_path, tls)?;
+        let context = context.build().into_context();
+
+        for hostname in &certificate.hostnames {
+            contexts.insert(hostname.to_lowercase(), context.clone());
+        }
+    }
+
+    if !contexts.is_empty() {
+        acceptor.set_servername_callback(move |ssl, _alert| {
+            let hostname = match ssl.servername(NameType::HOST_NAME) {
+                Some(hostname) => hostname.to_lowercase(),
+                // No SNI at all, the default certificate it is.
+                None => { return Ok(()) }
+            };
+
+            // An exact match wins over a wildcard one.
+            let wildcard = hostname.split_once('.').map(|(_, parent)| format!("*.{parent}"));
+            let context = contexts.get(&hostname)
+                .or_else(|| wildcard.and_then(|wildcard| contexts.get(&wildcard)));
+
+            if let Some(context) = context {
+                if ssl.set_ssl_context(context).is_err() {
+                    return Err(SniError::ALERT_FATAL);
+                }
+            }
+
+            Ok(())
+        });
+    }
+
+    Ok(acceptor.build())
+}
+
+/// Loads a certificate and key into a context, along with the client certificate settings.
+fn configure_context(context: &mut SslContextBuilder, cert_path: &str, key_path: &str, tls: &TlsSettings) -> Result<(), String> {
+    context.set_private_key_file(key_path, SslFiletype::PEM)
+        .map_err(|err| format!("Failed to load the key at {key_path}: {err}"))?;
+    context.set_certificate_chain_file(cert_path)
+        .map_err(|err| format!("Failed to load the certificate at {cert_path}: {err}"))?;
+    context.check_private_key()
+        .map_err(|err| format!("The key at {key_path} doesn't match the certificate at {cert_path}: {err}"))?;
+
+    // Ask clients for a certificate signed by our CA, so they can skip the password.
+    if tls.client_cert_mode != ClientCertMode::Off {
+        let ca_path = match &tls.client_ca_path {
+            Some(ca_path) => ca_path,
+            None => { return Err("client_cert_mode needs a client_ca_path to check certificates against".to_string()); }
+        };
+
+        context.set_ca_file(ca_path)
+            .map_err(|err| format!("Failed to load the client CA at {ca_path}: {err}"))?;
+        context.set_client_ca_list(X509Name::load_client_ca_file(ca_path)
+            .map_err(|err| format!("Failed to load the client CA at {ca_path}: {err}"))?);
+
+        let mode = match tls.client_cert_mode {
+            ClientCertMode::Required => SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT,
+            _ => SslVerifyMode::PEER
+        };
+        context.set_verify(mode);
+    }
+
+    // Once peers are verified, OpenSSL fails any resumed session without this.
+    context.set_session_id_context(b"mcserver-handler")
+        .map_err(|err| format!("Failed to set the session id context: {err}"))?;
+
+    Ok(())
+}
+
+/// When every certificate and key file was last changed, or None if one is missing.
+fn modified_times(tls: &TlsSettings) -> Option<Vec<SystemTime>> {
+    let mut paths = vec![&tls.cert_path, &tls.key_path];
+    for certificate in &tls.sni_certificates {
+        paths.push(&certificate.cert_path);
+        paths.push(&certificate.key_path);
+    }
+
+    paths.into_iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// Binds `addr` and hands every connection to `on_stream` across `threads` worker threads.
+/// `on_stream` returns false once the worker should stop.
+fn spawn_listener(addr: &str, threads: u32, timeout: Duration, on_stream: Arc<dyn Fn(TcpStream) -> bool + Send + Sync>) {
+    // Create the server listener
+    let listener = TcpListener::bind(addr)
+        .unwrap_or_else(|err| panic!("Failed to listen on {addr}: {err}"));
+
+    // Init the handler threads.
+    // Each thread does the TLS handshake and parsing itself, so a slow client
+    // only ever holds up one worker, never the main loop.
+    for _ in 0..threads {
+        let tcp_listener = listener.try_clone().unwrap();
+        let on_stream = on_stream.clone();
+        // Init the server here.
+        thread::spawn(move || {
+            for stream in tcp_listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => { continue }
+                };
+
+                // Don't let a client hold this worker forever.
+                if stream.set_read_timeout(Some(timeout)).is_err() || stream.set_write_timeout(Some(timeout)).is_err() {
+                    continue
+                }
+
+                if !on_stream(stream) {
+                    break;
+                }
+            }
+        });
+    }
+}
+
+/// A socket that gives up once `deadline` passes, however slowly the client trickles in or reads the response.
+/// Reading the request and writing the response each get `timeout`, so a client that stops reading
+/// can't hold up the main loop for longer than that.
+#[derive(Debug)]
+pub struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Instant,
+    timeout: Duration
+}
+
+impl DeadlineStream {
+    /// Gives the response its own `timeout`, since some pages take a while to be made.
+    fn renew(&mut self) {
+        self.deadline = Instant::now() + self.timeout;
+    }
+
+    /// What's left until the deadline, or an error once it has passed.
+    fn remaining(&self) -> std::io::Result<Duration> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "The client took too long"));
         }
+        Ok(remaining)
+    }
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
 
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stream.flush()
     }
+}
 
-    pub fn get_streams(&mut self) -> Vec<TcpStream> {
-        let mut streams = vec![];
+/// A client connection, either wrapped in TLS or not.
+pub enum Connection {
+    Tls(SslStream<DeadlineStream>),
+    Plain(DeadlineStream)
+}
 
-        while let Ok(stream) = self.tcp_receiver.try_recv() {
-            streams.push(stream);
+impl Connection {
+    fn renew_deadline(&mut self) {
+        match self {
+            Connection::Tls(stream) => stream.get_mut().renew(),
+            Connection::Plain(stream) => stream.renew()
         }
+    }
+}
 
-        streams
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tls(stream) => stream.read(buf),
+            Connection::Plain(stream) => stream.read(buf)
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Connection::Tls(stream) => stream.write(buf),
+            Connection::Plain(stream) => stream.write(buf)
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Connection::Tls(stream) => stream.flush(),
+            Connection::Plain(stream) => stream.flush()
+        }
     }
 }
 
 pub struct ServerStream {
-    pub tcp_stream: SslStream<TcpStream>,
+    pub tcp_stream: Connection,
     pub request: String,
+    // The common name of the client's certificate, already verified against our CA.
+    pub client_name: Option<String>,
+    // When the connection came in, to time the whole request.
+    received: Instant,
+    // What /metrics counts this under instead of its route, for listeners that answer any path.
+    metrics_route: Option<&'static str>,
     headers: Map<String, Value>,
     cookies: Map<String, Value>,
+    query: Map<String, Value>,
     content: Map<String, Value>
 }
 
-pub fn parse_stream(stream: TcpStream, server: &Server) -> Result<ServerStream, String> {
-    let mut stream = match server.acceptor.accept(stream.try_clone().unwrap()) {
-        Ok(str) => str,
-        Err(err) => { return Err(err.to_string()); }
+/// Reads a request, handshake and all, which has to be done within `timeout`.
+pub fn parse_stream(stream: TcpStream, acceptor: Option<&SslAcceptor>, timeout: Duration) -> Result<ServerStream, String> {
+    let received = Instant::now();
+    let stream = DeadlineStream { stream, deadline: received + timeout, timeout };
+
+    let mut stream = match acceptor {
+        Some(acceptor) => match acceptor.accept(stream) {
+            Ok(str) => Connection::Tls(str),
+            Err(err) => { return Err(err.to_string()); }
+        },
+        None => Connection::Plain(stream)
+    };
+
+    // Any certificate that made it through the handshake was verified, so we can trust its name.
+    // Names are mapped to users, so one with a NUL in it is ignored rather than cut short.
+    let client_name = match &stream {
+        Connection::Tls(tls) => tls.ssl().peer_certificate().and_then(|cert| {
+            cert.subject_name().entries_by_nid(Nid::COMMONNAME).next()
+                .map(|entry| entry.data().as_slice().to_vec())
+                .filter(|name| !name.contains(&0))
+                .and_then(|name| String::from_utf8(name).ok())
+        }),
+        Connection::Plain(_) => None
     };
 
     let mut buf_reader = BufReader::new(&mut stream);
 
     let mut http_request = vec![];
     let mut header_line = "".to_string();
+    let mut header_bytes = 0;
 
     loop {
-        match buf_reader.read_line(&mut header_line) {
-            Ok(_) => {},
+        // Reading one byte past the limit is enough to tell the headers are too long.
+        let limit = (MAX_HEADER_BYTES - header_bytes + 1) as u64;
+        match (&mut buf_reader).take(limit).read_line(&mut header_line) {
+            // The client hung up before finishing the headers.
+            Ok(0) => { return Err("Connection closed before the request was complete".to_string()); }
+            Ok(read) => header_bytes += read,
             Err(err) => { return Err(err.to_string()); }
         };
 
+        if header_bytes > MAX_HEADER_BYTES {
+            reject(buf_reader.get_mut(), "HTTP/1.1 431 Request Header Fields Too Large");
+            return Err("Request headers too large".to_string());
+        }
+
         // The final line is just /r/n
-        if header_line.len() == 2 {
+        if header_line.trim().is_empty() {
             break
         }
 
@@ -99,15 +430,25 @@ pub fn parse_stream(stream: TcpStream, server: &Server) -> Result<ServerStream,
         header_line = "".to_string();
     }
 
+    if http_request.is_empty() {
+        return Err("Empty request".to_string());
+    }
+
     let mut request = http_request[0].clone();
     request = request.trim().to_string();
     http_request.remove(0);
 
     let mut headers = Map::new();
     for request in &http_request {
-        let split: Vec<_> = request.trim().split(": ").collect();
+        let (key, value) = match request.trim().split_once(": ") {
+            Some(pair) => pair,
+            None => {
+                reject(buf_reader.get_mut(), "HTTP/1.1 400 Bad Request");
+                return Err(format!("Malformed header {request:?}"));
+            }
+        };
 
-        headers.insert(split[0].to_string().clone(), json!(split[1].to_string().clone()));
+        headers.insert(key.to_string(), json!(value.to_string()));
     }
 
     let mut cookies = Map::new();
@@ -117,9 +458,9 @@ pub fn parse_stream(stream: TcpStream, server: &Server) -> Result<ServerStream,
         let split_cookies: Vec<_> = cookie_str.split("; ").collect();
 
         for cookie_pair in split_cookies {
-            let split_pair: (&str, &str) = cookie_pair.split_once("=").unwrap();
-
-            cookies.insert(split_pair.0.to_string(), json!(split_pair.1));
+            if let Some(split_pair) = cookie_pair.split_once('=') {
+                cookies.insert(split_pair.0.to_string(), json!(split_pair.1));
+            }
         }
     }
 
@@ -127,36 +468,139 @@ pub fn parse_stream(stream: TcpStream, server: &Server) -> Result<ServerStream,
     let mut content = Map::new();
 
     if let Some(content_length) = headers.get("Content-Length") {
-        let content_length = content_length.as_str().unwrap().parse::<usize>().unwrap();
+        let content_length = match content_length.as_str().unwrap().parse::<usize>() {
+            Ok(length) => length,
+            Err(err) => {
+                reject(buf_reader.get_mut(), "HTTP/1.1 400 Bad Request");
+                return Err(format!("Bad Content-Length: {err}"));
+            }
+        };
+        if content_length > MAX_BODY_BYTES {
+            reject(buf_reader.get_mut(), "HTTP/1.1 413 Payload Too Large");
+            return Err(format!("Request body of {content_length} bytes is too large"));
+        }
 
         let mut read_buf = vec![0u8; content_length];
-        buf_reader.read_exact(&mut read_buf).unwrap();
+        if let Err(err) = buf_reader.read_exact(&mut read_buf) {
+            return Err(err.to_string());
+        }
 
-        let body = String::from_utf8(read_buf.to_vec()).unwrap();
+        let body = String::from_utf8_lossy(&read_buf).to_string();
 
         let split_body: Vec<_> = body.trim().split("\r\n").collect();
 
         for piece in split_body {
-            let pieces: (&str, &str) = piece.split_once("=").unwrap();
+            if let Some(pieces) = piece.split_once('=') {
+                content.insert(pieces.0.to_string(), json!(pieces.1.to_string()));
+            }
+        }
+    }
 
-            content.insert(pieces.0.to_string(), json!(pieces.1.to_string()));
+    // Pull the query string out of the request target, like /search?text=hi
+    let mut query = Map::new();
+    if let Some((_, query_string)) = request.split(' ').nth(1).and_then(|target| target.split_once('?')) {
+        for pair in query_string.split('&') {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            query.insert(url_decode(key), json!(url_decode(value)));
         }
     }
 
     Ok(ServerStream {
         tcp_stream: stream,
         request,
+        client_name,
+        received,
+        metrics_route: None,
         headers,
         cookies,
+        query,
         content
     })
 }
 
+/// Answers a request that won't be read any further, before there is a ServerStream to answer it with.
+fn reject(stream: &mut Connection, status_line: &str) {
+    let _ = stream.write_all(format!("{status_line}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n").as_bytes());
+}
+
+/// Decodes a url encoded query value, turning + into spaces and %XX into bytes.
+pub fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            // Exactly two hex digits, a sign or anything else leaves the % as it is.
+            b'%' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                let digit = |byte: u8| (byte as char).to_digit(16).unwrap_or(0) as u8;
+                decoded.push(digit(bytes[i + 1]) << 4 | digit(bytes[i + 2]));
+                i += 2;
+            }
+            byte => decoded.push(byte)
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Encodes a value so it can go in a query string.
+pub fn url_encode(value: &str) -> String {
+    value.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+        byte => format!("%{byte:02X}")
+    }).collect()
+}
+
 impl ServerStream {
     pub fn get_request(&self) -> String {
         self.request.clone()
     }
 
+    /// The method and path without the query string or HTTP version, like "GET /console".
+    pub fn get_route(&self) -> String {
+        let mut parts = self.request.split(' ');
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+        let path = path.split_once('?').map(|(path, _)| path).unwrap_or(path);
+
+        format!("{method} {path}")
+    }
+
+    /// Counts the request under `route` in /metrics, so made up paths can't add labels without end.
+    pub fn counted_as(mut self, route: &'static str) -> Self {
+        self.metrics_route = Some(route);
+        self
+    }
+
+    pub fn get_query(&self, key: &str) -> Option<String> {
+        if let Some(value) = self.query.get(key) {
+            if let Some(str_value) = value.as_str() {
+                return Some(str_value.to_string());
+            }
+        }
+        None
+    }
+
+    /// Where this same request lives on the https listener.
+    pub fn https_location(&self, https_port: &str) -> String {
+        let host = self.get_header("Host").unwrap_or_default();
+        // Drop whatever port the request came in on, it belongs to the plain listener.
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name.to_string(),
+            _ => host
+        };
+
+        let path = self.request.split(' ').nth(1).unwrap_or("/");
+
+        match https_port {
+            "" | "443" => format!("https://{host}{path}"),
+            port => format!("https://{host}:{port}{path}")
+        }
+    }
+
     pub fn get_header(&self, key: &str) -> Option<String> {
         if let Some(value) = self.headers.get(key) {
             if let Some(str_value) = value.as_str() {
@@ -198,6 +642,39 @@ impl ServerStream {
 
         response.push_str(&format!("Content-Length: {length}\r\n\r\n{contents}"));
 
-        self.tcp_stream.write_all(response.as_bytes()).unwrap();
+        // The client may have already gone away, which isn't worth crashing over.
+        self.tcp_stream.renew_deadline();
+        if let Err(err) = self.tcp_stream.write_all(response.as_bytes()) {
+            log!("Failed to write response: {err}");
+        }
+
+        let code = status_line.split_whitespace().nth(1).and_then(|code| code.parse().ok()).unwrap_or(0);
+        let route = self.metrics_route.map(str::to_string).unwrap_or_else(|| self.get_route());
+        prometheus::record_request(&route, code, self.received.elapsed().as_secs_f64());
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_urls() {
+        assert_eq!(url_decode("a+b%20c%2Fd"), "a b c/d");
+        assert_eq!(url_decode("%e2%9c%93"), "\u{2713}");
+    }
+
+    #[test]
+    fn leaves_bad_escapes_alone() {
+        assert_eq!(url_decode("%+1"), "% 1");
+        assert_eq!(url_decode("%-1x"), "%-1x");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%4"), "%4");
+        assert_eq!(url_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let value = "Steve & Alex = 100% §a✓";
+        assert_eq!(url_decode(&url_encode(value)), value);
     }
-}
\ No newline at end of file
+}
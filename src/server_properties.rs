@@ -0,0 +1,446 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::json;
+use crate::access_lists::write_atomic;
+
+pub const SERVER_PROPERTIES: &str = "server.properties";
+
+/// What a property may hold.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Kind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Choice { options: &'static [&'static str] },
+    Text
+}
+
+/// A key vanilla knows about.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct PropertySchema {
+    pub key: &'static str,
+    #[serde(flatten)]
+    pub kind: Kind
+}
+
+const fn property(key: &'static str, kind: Kind) -> PropertySchema {
+    PropertySchema { key, kind }
+}
+
+const BOOL: Kind = Kind::Bool;
+const TEXT: Kind = Kind::Text;
+const PORT: Kind = Kind::Int { min: 1, max: 65535 };
+const fn int(min: i64, max: i64) -> Kind {
+    Kind::Int { min, max }
+}
+
+/// Every key a vanilla server writes. Anything else is kept and shown as plain text.
+pub const SCHEMA: &[PropertySchema] = &[
+    property("allow-flight", BOOL),
+    property("allow-nether", BOOL),
+    property("broadcast-console-to-ops", BOOL),
+    property("broadcast-rcon-to-ops", BOOL),
+    property("difficulty", Kind::Choice { options: &["peaceful", "easy", "normal", "hard"] }),
+    property("enable-command-block", BOOL),
+    property("enable-jmx-monitoring", BOOL),
+    property("enable-query", BOOL),
+    property("enable-rcon", BOOL),
+    property("enable-status", BOOL),
+    property("enforce-secure-profile", BOOL),
+    property("enforce-whitelist", BOOL),
+    property("entity-broadcast-range-percentage", int(10, 1000)),
+    property("force-gamemode", BOOL),
+    property("function-permission-level", int(1, 4)),
+    property("gamemode", Kind::Choice { options: &["survival", "creative", "adventure", "spectator"] }),
+    property("generate-structures", BOOL),
+    property("generator-settings", TEXT),
+    property("hardcore", BOOL),
+    property("hide-online-players", BOOL),
+    property("initial-disabled-packs", TEXT),
+    property("initial-enabled-packs", TEXT),
+    property("level-name", TEXT),
+    property("level-seed", TEXT),
+    property("level-type", TEXT),
+    property("log-ips", BOOL),
+    property("max-chained-neighbor-updates", int(-1, i32::MAX as i64)),
+    property("max-players", int(0, i32::MAX as i64)),
+    property("max-tick-time", int(-1, i64::MAX)),
+    property("max-world-size", int(1, 29999984)),
+    property("motd", TEXT),
+    property("network-compression-threshold", int(-1, i32::MAX as i64)),
+    property("online-mode", BOOL),
+    property("op-permission-level", int(0, 4)),
+    property("player-idle-timeout", int(0, i32::MAX as i64)),
+    property("prevent-proxy-connections", BOOL),
+    property("pvp", BOOL),
+    property("query.port", PORT),
+    property("rate-limit", int(0, i32::MAX as i64)),
+    property("rcon.password", TEXT),
+    property("rcon.port", PORT),
+    property("require-resource-pack", BOOL),
+    property("resource-pack", TEXT),
+    property("resource-pack-id", TEXT),
+    property("resource-pack-prompt", TEXT),
+    property("resource-pack-sha1", TEXT),
+    property("server-ip", TEXT),
+    property("server-port", PORT),
+    property("simulation-distance", int(3, 32)),
+    property("spawn-animals", BOOL),
+    property("spawn-monsters", BOOL),
+    property("spawn-npcs", BOOL),
+    property("spawn-protection", int(0, i32::MAX as i64)),
+    property("sync-chunk-writes", BOOL),
+    property("text-filtering-config", TEXT),
+    property("use-native-transport", BOOL),
+    property("view-distance", int(3, 32)),
+    property("white-list", BOOL)
+];
+
+pub fn schema(key: &str) -> Option<&'static PropertySchema> {
+    SCHEMA.iter().find(|property| property.key == key)
+}
+
+/// Checks a value against the schema. Unknown keys take anything.
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    let kind = match schema(key) {
+        Some(property) => property.kind,
+        None => return Ok(())
+    };
+
+    match kind {
+        Kind::Bool if value != "true" && value != "false" => Err(format!("{key} has to be true or false")),
+        Kind::Int { min, max } => match value.parse::<i64>() {
+            Ok(number) if (min..=max).contains(&number) => Ok(()),
+            _ => Err(format!("{key} has to be a whole number from {min} to {max}"))
+        },
+        Kind::Choice { options } if !options.contains(&value) => Err(format!("{key} has to be one of {}", options.join(", "))),
+        _ => Ok(())
+    }
+}
+
+/// One logical line of the file.
+#[derive(Clone, Debug)]
+enum Line {
+    // A comment or a blank line, kept exactly as it was.
+    Other(String),
+    // `raw` is what was in the file, so untouched entries are written back the same way.
+    Entry { key: String, value: String, raw: String }
+}
+
+/// A server.properties file that keeps its comments and order when written back.
+#[derive(Clone, Debug, Default)]
+pub struct Properties {
+    lines: Vec<Line>
+}
+
+impl Properties {
+    /// Reads the file, or starts empty if the server hasn't made one yet.
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        match fs::read_to_string(dir.join(SERVER_PROPERTIES)) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err)
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        write_atomic(&dir.join(SERVER_PROPERTIES), self.to_string().as_bytes())
+    }
+
+    pub fn parse(contents: &str) -> Self {
+        let mut lines = vec![];
+        let mut physical = contents.lines();
+
+        while let Some(line) = physical.next() {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                lines.push(Line::Other(line.to_string()));
+                continue;
+            }
+
+            // A line ending in an odd number of backslashes carries on onto the next one.
+            let mut raw = line.to_string();
+            let mut logical = trimmed.to_string();
+            while ends_with_continuation(&logical) {
+                logical.pop();
+                match physical.next() {
+                    Some(next) => {
+                        raw.push('\n');
+                        raw.push_str(next);
+                        logical.push_str(next.trim_start());
+                    }
+                    None => break
+                }
+            }
+
+            let (key, value) = split_entry(&logical);
+            lines.push(Line::Entry { key: unescape(key), value: unescape(value), raw });
+        }
+
+        Self { lines }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.lines.iter().find_map(|line| match line {
+            Line::Entry { key: entry_key, value, .. } if entry_key == key => Some(value.as_str()),
+            _ => None
+        })
+    }
+
+    /// Changes a value in place, or adds it at the end. Returns whether anything changed.
+    pub fn set(&mut self, key: &str, value: &str) -> bool {
+        for line in &mut self.lines {
+            if let Line::Entry { key: entry_key, value: entry_value, raw } = line {
+                if entry_key == key {
+                    if entry_value == value {
+                        return false;
+                    }
+                    *entry_value = value.to_string();
+                    *raw = format!("{}={}", escape(key, true), escape(value, false));
+                    return true;
+                }
+            }
+        }
+
+        self.lines.push(Line::Entry { key: key.to_string(), value: value.to_string(), raw: format!("{}={}", escape(key, true), escape(value, false)) });
+        true
+    }
+
+    /// Every key and value, in file order.
+    pub fn entries(&self) -> Vec<(&str, &str)> {
+        self.lines.iter().filter_map(|line| match line {
+            Line::Entry { key, value, .. } => Some((key.as_str(), value.as_str())),
+            Line::Other(_) => None
+        }).collect()
+    }
+
+    /// Every key the form should show: the ones in the file, then any known ones it doesn't have yet.
+    pub fn keys(&self) -> Vec<String> {
+        let mut keys: Vec<String> = self.entries().into_iter().map(|(key, _)| key.to_string()).collect();
+        for property in SCHEMA {
+            if !keys.iter().any(|key| key == property.key) {
+                keys.push(property.key.to_string());
+            }
+        }
+        keys
+    }
+
+    /// The fields of the form, showing `submitted` values over the saved ones along with their errors.
+    pub fn fields(&self, submitted: &HashMap<String, String>, errors: &HashMap<String, String>) -> Vec<serde_json::Value> {
+        self.keys().into_iter().map(|key| {
+            let value = submitted.get(&key).map(String::as_str).or(self.get(&key)).unwrap_or_default();
+            let mut field = json!({
+                "key": key,
+                "value": value,
+                "error": errors.get(&key),
+                // Not in the file, so leaving it empty keeps it out.
+                "unset": self.get(&key).is_none(),
+                "type": "text"
+            });
+            if let Some(property) = schema(&key) {
+                if let serde_json::Value::Object(kind) = json!(property.kind) {
+                    field.as_object_mut().unwrap().extend(kind);
+                }
+            }
+            field
+        }).collect()
+    }
+}
+
+impl std::fmt::Display for Properties {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            match line {
+                Line::Other(raw) | Line::Entry { raw, .. } => writeln!(f, "{raw}")?
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// Splits at the first unescaped `=`, `:` or whitespace, the way java.util.Properties does.
+fn split_entry(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (index, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '=' | ':' | ' ' | '\t' => {
+                let key = &line[..index];
+                let rest = line[index..].trim_start_matches([' ', '\t']);
+                // Whitespace can be followed by the real separator.
+                let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest).trim_start_matches([' ', '\t']);
+                return (key, rest);
+            }
+            _ => {}
+        }
+    }
+
+    (line, "")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\x0c'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(c) => result.push(c),
+                    None => result.push_str(&hex)
+                }
+            }
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+
+    result
+}
+
+/// Escapes text the way Minecraft writes it, so the server reads back exactly what was set.
+fn escape(text: &str, is_key: bool) -> String {
+    let mut result = String::new();
+
+    for (index, c) in text.chars().enumerate() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\x0c' => result.push_str("\\f"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            // Spaces only matter in keys, or at the start of a value.
+            ' ' if is_key || index == 0 => result.push_str("\\ "),
+            // Newer servers read the file as UTF-8, so only control characters need escaping.
+            c if c.is_control() => result.push_str(&format!("\\u{:04X}", c as u32)),
+            c => result.push(c)
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = "#Minecraft server properties
+#Mon Oct 19 12:00:00 UTC 2026
+! an old style comment
+
+motd=A Minecraft Server
+view-distance 10
+level-name:world
+server-port = 25565
+level-seed=one\\
+    two\\
+    three
+resource-pack-prompt=caf\\u00e9 \\u00fcber
+key\\ with\\ spaces=value\\=with\\:separators
+empty-value=
+";
+
+    #[test]
+    fn writes_back_exactly_what_it_read() {
+        assert_eq!(Properties::parse(FILE).to_string(), FILE);
+    }
+
+    #[test]
+    fn reads_every_kind_of_entry() {
+        let properties = Properties::parse(FILE);
+
+        assert_eq!(properties.get("motd"), Some("A Minecraft Server"));
+        assert_eq!(properties.get("view-distance"), Some("10"));
+        assert_eq!(properties.get("level-name"), Some("world"));
+        assert_eq!(properties.get("server-port"), Some("25565"));
+        assert_eq!(properties.get("level-seed"), Some("onetwothree"));
+        assert_eq!(properties.get("resource-pack-prompt"), Some("café über"));
+        assert_eq!(properties.get("key with spaces"), Some("value=with:separators"));
+        assert_eq!(properties.get("empty-value"), Some(""));
+        // Comments aren't entries.
+        assert_eq!(properties.entries().len(), 8);
+    }
+
+    #[test]
+    fn set_changes_an_existing_key_in_place() {
+        let mut properties = Properties::parse(FILE);
+
+        assert!(properties.set("view-distance", "12"));
+        assert!(!properties.set("view-distance", "12"));
+
+        let expected = FILE.replace("view-distance 10", "view-distance=12");
+        assert_eq!(properties.to_string(), expected);
+    }
+
+    #[test]
+    fn set_adds_a_new_key_at_the_end() {
+        let mut properties = Properties::parse(FILE);
+
+        assert!(properties.set("pvp", "false"));
+        assert_eq!(properties.to_string(), format!("{FILE}pvp=false\n"));
+        assert_eq!(properties.entries().last(), Some(&("pvp", "false")));
+    }
+
+    #[test]
+    fn set_escapes_what_it_writes() {
+        let mut properties = Properties::default();
+        properties.set("motd", " Hi: #1 = \\o/\n");
+
+        assert_eq!(properties.to_string(), "motd=\\ Hi\\: \\#1 \\= \\\\o/\\n\n");
+        assert_eq!(Properties::parse(&properties.to_string()).get("motd"), Some(" Hi: #1 = \\o/\n"));
+    }
+
+    #[test]
+    fn validate_accepts_the_bounds_and_nothing_past_them() {
+        assert!(validate("view-distance", "3").is_ok());
+        assert!(validate("view-distance", "32").is_ok());
+        assert!(validate("view-distance", "2").is_err());
+        assert!(validate("view-distance", "33").is_err());
+
+        assert!(validate("server-port", "1").is_ok());
+        assert!(validate("server-port", "65535").is_ok());
+        assert!(validate("server-port", "0").is_err());
+        assert!(validate("server-port", "65536").is_err());
+
+        assert!(validate("max-tick-time", "-1").is_ok());
+        assert!(validate("max-tick-time", &i64::MAX.to_string()).is_ok());
+        assert!(validate("max-tick-time", "-2").is_err());
+
+        assert_eq!(validate("op-permission-level", "five"), Err("op-permission-level has to be a whole number from 0 to 4".to_string()));
+    }
+
+    #[test]
+    fn validate_checks_bools_and_choices() {
+        assert!(validate("pvp", "true").is_ok());
+        assert!(validate("pvp", "yes").is_err());
+        assert!(validate("difficulty", "hard").is_ok());
+        assert!(validate("difficulty", "Hard").is_err());
+        // Anything goes for keys vanilla doesn't know.
+        assert!(validate("some-mod-setting", "whatever").is_ok());
+    }
+}
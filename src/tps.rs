@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use crate::game_events::GameEvent;
+
+// What a server runs at when it keeps up, and the most any tps command reports.
+const FULL_TPS: f64 = 20.0;
+// Vanilla waits at least 15 seconds between "Can't keep up!" warnings,
+// so warnings further apart than this are taken as separate bouts of lag.
+const WARNING_GAP: Duration = Duration::from_secs(60);
+
+/// How fast the server was ticking at one point in time.
+#[derive(Clone, Debug, Serialize)]
+pub struct TickSample {
+    pub time: DateTime<Local>,
+    pub tps: f64,
+    // Milliseconds per tick, for the commands that say.
+    pub mspt: Option<f64>
+}
+
+/// A "Can't keep up!" warning from the server.
+#[derive(Clone, Debug, Serialize)]
+pub struct LagWarning {
+    pub time: DateTime<Local>,
+    pub milliseconds: u64,
+    pub ticks: u64
+}
+
+/// Keeps the newest `max_samples` tick samples and lag warnings,
+/// and works out when TPS has been below `alert_below` for long enough to raise an alert.
+/// Without samples, warnings that keep coming for that long raise it instead.
+pub struct TickMonitor {
+    samples: VecDeque<TickSample>,
+    warnings: VecDeque<LagWarning>,
+    max_samples: usize,
+    // Every warning since the handler started, for /metrics.
+    warnings_total: u64,
+
+    alert_below: f64,
+    alert_after: Duration,
+    // When the samples first went below alert_below, cleared once one is back above it.
+    below_since: Option<Instant>,
+    // Whether warnings raise alerts, for when there are no samples to go by.
+    alert_on_warnings: bool,
+    // When the current bout of warnings started, and when the last one came.
+    warned_since: Option<Instant>,
+    last_warning: Option<Instant>,
+    lagging: bool
+}
+
+impl TickMonitor {
+    pub fn new(alert_below: f64, alert_after: Duration, max_samples: usize, alert_on_warnings: bool) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            warnings: VecDeque::new(),
+            max_samples,
+            warnings_total: 0,
+
+            alert_below,
+            alert_after,
+            below_since: None,
+            alert_on_warnings,
+            warned_since: None,
+            last_warning: None,
+            lagging: false
+        }
+    }
+
+    /// Keeps a sample, and returns the alert it raises or clears, if any.
+    /// Vanilla only reports the time per tick, so TPS is worked out from that when it's missing.
+    pub fn record(&mut self, tps: Option<f64>, mspt: Option<f64>) -> Option<GameEvent> {
+        let tps = tps.or_else(|| mspt.map(|mspt| if mspt > 0.0 { (1000.0 / mspt).min(FULL_TPS) } else { FULL_TPS }))?;
+
+        push_bounded(&mut self.samples, TickSample { time: Local::now(), tps, mspt }, self.max_samples);
+
+        if tps >= self.alert_below {
+            self.below_since = None;
+            if self.lagging {
+                self.lagging = false;
+                return Some(GameEvent::LagRecovered { tps: Some(tps) });
+            }
+            return None;
+        }
+
+        let below_since = *self.below_since.get_or_insert_with(Instant::now);
+        if !self.lagging && below_since.elapsed() >= self.alert_after {
+            self.lagging = true;
+            return Some(GameEvent::LagAlert { tps: Some(tps), seconds: below_since.elapsed().as_secs() });
+        }
+        None
+    }
+
+    /// Keeps a warning, and returns the alert it raises, if warnings raise them.
+    pub fn warn(&mut self, milliseconds: u64, ticks: u64) -> Option<GameEvent> {
+        self.warnings_total += 1;
+        push_bounded(&mut self.warnings, LagWarning { time: Local::now(), milliseconds, ticks }, self.max_samples);
+
+        let now = Instant::now();
+        if self.last_warning.is_none_or(|last| now - last > WARNING_GAP) {
+            self.warned_since = Some(now);
+        }
+        self.last_warning = Some(now);
+
+        let warned_since = self.warned_since?;
+        if self.alert_on_warnings && !self.lagging && warned_since.elapsed() >= self.alert_after {
+            self.lagging = true;
+            return Some(GameEvent::LagAlert { tps: None, seconds: warned_since.elapsed().as_secs() });
+        }
+        None
+    }
+
+    /// Clears an alert raised by warnings once they've stopped coming, returning the event that says so.
+    pub fn check(&mut self) -> Option<GameEvent> {
+        let quiet = self.last_warning.is_none_or(|last| last.elapsed() > WARNING_GAP);
+        if self.alert_on_warnings && self.lagging && quiet {
+            self.lagging = false;
+            self.warned_since = None;
+            return Some(GameEvent::LagRecovered { tps: None });
+        }
+        None
+    }
+
+    /// Forgets whether the last process was lagging, so the next one starts with a clean slate.
+    /// The samples stay, to compare the two.
+    pub fn reset(&mut self) {
+        self.below_since = None;
+        self.warned_since = None;
+        self.last_warning = None;
+        self.lagging = false;
+    }
+
+    pub fn latest(&self) -> Option<&TickSample> {
+        self.samples.back()
+    }
+
+    /// Every sample kept, oldest first.
+    pub fn history(&self) -> Vec<TickSample> {
+        self.samples.iter().cloned().collect()
+    }
+
+    /// Every warning kept, oldest first.
+    pub fn warnings(&self) -> Vec<LagWarning> {
+        self.warnings.iter().cloned().collect()
+    }
+
+    pub fn warnings_total(&self) -> u64 {
+        self.warnings_total
+    }
+
+    pub fn alert_below(&self) -> f64 {
+        self.alert_below
+    }
+
+    /// Whether a lag alert was raised and hasn't cleared yet.
+    pub fn lagging(&self) -> bool {
+        self.lagging
+    }
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, value: T, max: usize) {
+    queue.push_back(value);
+    while queue.len() > max {
+        queue.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_samples_and_works_out_tps() {
+        let mut monitor = TickMonitor::new(18.0, Duration::from_secs(60), 2, false);
+        assert_eq!(monitor.record(None, None), None);
+        assert!(monitor.latest().is_none());
+
+        monitor.record(Some(19.5), None);
+        monitor.record(None, Some(100.0));
+        assert_eq!(monitor.latest().unwrap().tps, 10.0);
+        // Faster than 50ms per tick still only counts as full speed.
+        monitor.record(None, Some(20.0));
+        assert_eq!(monitor.latest().unwrap().tps, 20.0);
+
+        // Only max_samples are kept.
+        let history = monitor.history();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].tps, 10.0);
+    }
+
+    #[test]
+    fn alerts_once_below_for_long_enough() {
+        let mut monitor = TickMonitor::new(18.0, Duration::ZERO, 10, false);
+        assert_eq!(monitor.record(Some(19.0), None), None);
+        assert_eq!(monitor.record(Some(12.0), None), Some(GameEvent::LagAlert { tps: Some(12.0), seconds: 0 }));
+        assert!(monitor.lagging());
+        // Still lagging, so it isn't raised again.
+        assert_eq!(monitor.record(Some(11.0), None), None);
+        assert_eq!(monitor.record(Some(19.9), None), Some(GameEvent::LagRecovered { tps: Some(19.9) }));
+        assert!(!monitor.lagging());
+
+        let mut monitor = TickMonitor::new(18.0, Duration::from_secs(60), 10, false);
+        assert_eq!(monitor.record(Some(5.0), None), None);
+        assert!(!monitor.lagging());
+    }
+
+    #[test]
+    fn warnings_alert_without_samples() {
+        let mut monitor = TickMonitor::new(18.0, Duration::ZERO, 10, true);
+        assert_eq!(monitor.warn(2500, 50), Some(GameEvent::LagAlert { tps: None, seconds: 0 }));
+        assert_eq!(monitor.warn(3000, 60), None);
+        assert_eq!(monitor.warnings().len(), 2);
+        assert_eq!(monitor.warnings_total(), 2);
+        // The last warning was just now, so it hasn't recovered yet.
+        assert_eq!(monitor.check(), None);
+        assert!(monitor.lagging());
+
+        // Only counted when there's no tps_command.
+        let mut monitor = TickMonitor::new(18.0, Duration::ZERO, 10, false);
+        assert_eq!(monitor.warn(2500, 50), None);
+        assert_eq!(monitor.check(), None);
+        assert_eq!(monitor.warnings_total(), 1);
+
+        // A single warning isn't lasting lag.
+        let mut monitor = TickMonitor::new(18.0, Duration::from_secs(60), 10, true);
+        assert_eq!(monitor.warn(2500, 50), None);
+    }
+
+    #[test]
+    fn reset_forgets_the_alert_but_keeps_history() {
+        let mut monitor = TickMonitor::new(18.0, Duration::ZERO, 10, true);
+        monitor.record(Some(10.0), None);
+        monitor.warn(2500, 50);
+        assert!(monitor.lagging());
+
+        monitor.reset();
+        assert!(!monitor.lagging());
+        assert_eq!(monitor.check(), None);
+        assert_eq!(monitor.history().len(), 1);
+        assert_eq!(monitor.warnings().len(), 1);
+        // A new bout of lag raises a new alert.
+        assert!(matches!(monitor.record(Some(10.0), None), Some(GameEvent::LagAlert { .. })));
+    }
+}